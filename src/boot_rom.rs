@@ -0,0 +1,57 @@
+use std::{fs, io, result};
+
+use thiserror::Error;
+
+use crate::model::Model;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Could not read boot ROM: {0}.")]
+    Loading(#[from] io::Error),
+    #[error("Boot ROM size {size:#X} does not match the {model} boot ROM size {expected:#X}.")]
+    InvalidSize {
+        model: Model,
+        size: usize,
+        expected: usize,
+    },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// Boot ROM mapped over the cartridge until it gets disabled through 0xFF50.
+///
+/// DMG0, DMG, MGB and SGB boot ROMs are 256 bytes mapped at 0x0000-0x00FF. CGB boot ROMs are
+/// 2304 bytes and are also mapped at 0x0200-0x08FF, leaving the cartridge header visible.
+#[derive(Debug, Clone)]
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn from_file(path: &str, model: Model) -> Result<Self> {
+        let data = fs::read(path)?;
+        if data.len() != model.boot_rom_size() {
+            return Err(Error::InvalidSize {
+                model,
+                size: data.len(),
+                expected: model.boot_rom_size(),
+            });
+        }
+        Ok(Self { data })
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    /// Returns the byte mapped at the address, or `None` if the cartridge shows through.
+    pub fn read_8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0000..=0x00FF => Some(self.data[address as usize]),
+            0x0200..=0x08FF if self.is_cgb() => Some(self.data[address as usize]),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::interrupt::InterruptController;
+
+/// Memory bus as seen by the CPU.
+///
+/// Reads, writes and idle cycles each take one M-cycle, during which the rest of the system
+/// advances by 4 T-cycles.
+pub trait Bus {
+    fn read_8(&mut self, address: u16) -> u8;
+    fn write_8(&mut self, address: u16, value: u8);
+
+    /// Internal CPU cycle without memory access.
+    fn idle(&mut self);
+
+    /// Reads a byte without going through the CPU access rules, for debugging tools.
+    fn peek_8(&self, address: u16) -> u8;
+
+    /// Interrupt controller the CPU services interrupts from.
+    fn interrupts(&mut self) -> &mut InterruptController;
+
+    /// Enters STOP mode: DIV is reset and the timer and the PPU stop until a selected joypad
+    /// line goes low.
+    fn stop(&mut self);
+
+    fn is_stopped(&self) -> bool;
+}
@@ -1,9 +1,11 @@
-use std::{fs, io, ops::Range, result};
+use std::{fs, io, result};
 
 use core::fmt::Debug;
 use thiserror::Error;
 use tracing::error;
 
+use crate::save_state::{self, Reader, Snapshot, Writer};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Could not read cartridge: {0}.")]
@@ -22,30 +24,34 @@ pub type Result<T> = result::Result<T, Error>;
 // Cartridge Trait
 /////////
 
-pub trait Cartridge: Send {
+/// The ROM is not part of the save state, only the RAM and the banking registers are.
+pub trait Cartridge: Send + Snapshot {
     fn write_8(&mut self, address: u16, value: u8);
-    fn write_16(&mut self, address: u16, value: u16);
     fn read_8(&self, address: u16) -> u8;
-    fn read_16(&self, address: u16) -> u16;
     fn dump_rom(&self) -> Vec<u8>;
     fn dump_ram(&self) -> Vec<u8>;
-    fn borrow_rom(&self) -> &[u8];
+    fn load_ram(&mut self, ram: &[u8]);
+    fn has_battery(&self) -> bool;
 }
 
 impl Debug for dyn Cartridge {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        let mbc = self.dump_rom()[0x0147];
+        // The header is in bank 0, mapped whatever the banking registers hold
+        let mbc = self.read_8(0x0147);
         match mbc {
             0x00 => write!(f, "No MBC"),
             0x01..=0x03 => write!(f, "MBC1"),
-            _ => unreachable!(),
+            _ => write!(f, "Empty slot"),
         }
     }
 }
 
 pub fn from_file(path: &str) -> Result<Box<dyn Cartridge>> {
-    let rom = fs::read(path).map_err(|err| Error::Loading(err))?;
+    let rom = fs::read(path).map_err(Error::Loading)?;
+    from_rom(rom)
+}
 
+pub fn from_rom(rom: Vec<u8>) -> Result<Box<dyn Cartridge>> {
     if rom.len() < 0x8000 {
         return Err(Error::InvalidRomSize);
     }
@@ -59,11 +65,20 @@ pub fn from_file(path: &str) -> Result<Box<dyn Cartridge>> {
     }
 }
 
+/// Reads like an empty cartridge slot, every byte is pulled up to 0xFF.
+pub fn empty() -> Box<dyn Cartridge> {
+    Box::new(CartridgeROM {
+        rom: vec![0xFF; 0x8000],
+        ram: [0xFF; 0x2000],
+        _rom_size: 0,
+    })
+}
+
 // Test roms
 
 #[allow(dead_code)]
 pub fn test_rom_from_file(path: &str) -> Result<Box<dyn Cartridge>> {
-    let rom = fs::read(path).map_err(|err| Error::Loading(err))?;
+    let rom = fs::read(path).map_err(Error::Loading)?;
 
     let mut new_rom = vec![0u8; 0x8000];
     for (i, elem) in rom.iter().enumerate() {
@@ -115,36 +130,15 @@ impl Cartridge for CartridgeROM {
         }
     }
 
-    fn write_16(&mut self, address: u16, value: u16) {
-        match address {
-            0xA000..=0xBFFF => {
-                let bytes = value.to_le_bytes();
-                self.ram[(address - 0xA000) as usize] = bytes[0];
-                self.ram[((address - 0xA000) + 1) as usize] = bytes[1];
-            }
-            _ => {
-                error!(
-                    "Tried to write to ROM Cartridge ! Address: {:04X} Value: {}",
-                    address, value
-                );
-            }
-        }
-    }
-
     fn read_8(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x7FFF => self.rom[address as usize],
+            0x0000..=0x7FFF => self.rom.get(address as usize).copied().unwrap_or(0xFF),
             0xA000..=0xBFFF => self.ram[(address - 0xA000) as usize],
-            _ => panic!("Tried to read out of cartridge bounds"),
+            // Not decoded by the cartridge, the data bus is pulled up
+            _ => 0xFF,
         }
     }
 
-    fn read_16(&self, address: u16) -> u16 {
-        let n1 = self.read_8(address);
-        let n2 = self.read_8(address + 1);
-        u16::from_le_bytes([n1, n2])
-    }
-
     fn dump_rom(&self) -> Vec<u8> {
         self.rom.clone()
     }
@@ -153,8 +147,20 @@ impl Cartridge for CartridgeROM {
         [0u8; 0x2000].to_vec()
     }
 
-    fn borrow_rom(&self) -> &[u8] {
-        &self.rom
+    fn load_ram(&mut self, _ram: &[u8]) {}
+
+    fn has_battery(&self) -> bool {
+        false
+    }
+}
+
+impl Snapshot for CartridgeROM {
+    fn save(&self, state: &mut Writer) {
+        state.bytes(&self.ram);
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        state.bytes(&mut self.ram)
     }
 }
 
@@ -166,17 +172,19 @@ impl Cartridge for CartridgeROM {
 pub struct CartridgeMBC1 {
     rom: Vec<u8>,
     ram: Vec<u8>,
-    _rom_bank_count: u32,
+    battery: bool,
     _ram_bank_count: u32,
-    selected_rom_bank: u32,
-    selected_ram_bank: u32,
+    /// Lower 5 bits of the ROM bank mapped at 0x4000-0x7FFF.
+    rom_bank: u8,
+    /// Upper 2 bits of the ROM bank, or the RAM bank in RAM banking mode.
+    upper_bank: u8,
+    /// Applies `upper_bank` to 0x0000-0x3FFF and to the RAM too, instead of only to
+    /// 0x4000-0x7FFF.
+    ram_banking_mode: bool,
 }
 
 impl CartridgeMBC1 {
     pub fn new(rom: Vec<u8>) -> Result<Self> {
-        let rom_size = rom[0x0148];
-        let _rom_bank_count = 1 << (rom_size + 1);
-
         let ram_size = rom[0x0149];
         let (ram, _ram_bank_count) = match ram_size {
             0x00 => (vec![0u8; 0], 0),
@@ -188,60 +196,63 @@ impl CartridgeMBC1 {
                 return Err(Error::InvalidHeader("Invalid RAM size header."));
             }
         };
+        let battery = rom[0x0147] == 0x03;
         Ok(Self {
             rom,
             ram,
-            _rom_bank_count,
+            battery,
             _ram_bank_count,
-            selected_rom_bank: 1,
+            rom_bank: 1,
             ..Default::default()
         })
     }
 
     fn select_rom_bank(&mut self, value: u8) {
+        // Bank 0 can't be mapped at 0x4000, only the 5 bits written are compared with 0
         let mut value = value & 0x1F;
         if value == 0 {
             value = 1;
         }
-        self.selected_rom_bank = value as u32;
+        self.rom_bank = value;
     }
 
-    fn select_ram_bank(&mut self, value: u8) {
-        let value = value & 0x03;
-
-        self.selected_ram_bank = value as u32;
+    fn select_upper_bank(&mut self, value: u8) {
+        self.upper_bank = value & 0x03;
     }
 
-    fn rom_read_8(&self, address: u16) -> u8 {
-        self.rom[self.selected_rom_bank as usize * 0x4000 + address as usize - 0x4000]
+    fn select_banking_mode(&mut self, value: u8) {
+        self.ram_banking_mode = value & 0x01 != 0;
     }
 
-    fn rom_read_16(&self, address: u16) -> u16 {
-        let n1 = self.read_8(address);
-        let n2 = self.read_8(address + 1);
-        u16::from_le_bytes([n1, n2])
+    /// Reads ROM bank `bank`. Bank numbers past the end of the ROM wrap around, as the chip
+    /// ignores the address lines it doesn't have.
+    fn rom_read_8(&self, bank: usize, address: u16) -> u8 {
+        let bank = bank % (self.rom.len() / 0x4000);
+        let offset = bank * 0x4000 + (address & 0x3FFF) as usize;
+        self.rom.get(offset).copied().unwrap_or(0xFF)
     }
 
-    fn ram_write_8(&mut self, address: u16, value: u8) {
-        self.ram[self.selected_ram_bank as usize * 0x2000 + address as usize - 0xA000] = value;
+    /// Offset of `address` in the RAM, which wraps around when smaller than the bank.
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let bank = match self.ram_banking_mode {
+            true => self.upper_bank as usize,
+            false => 0,
+        };
+        Some((bank * 0x2000 + (address - 0xA000) as usize) % self.ram.len())
     }
 
-    fn ram_write_16(&mut self, address: u16, value: u16) {
-        let bytes = value.to_le_bytes();
-
-        self.ram[self.selected_ram_bank as usize * 0x2000 + address as usize - 0xA000] = bytes[0];
-        self.ram[self.selected_ram_bank as usize * 0x2000 + (address + 1) as usize - 0xA000] =
-            bytes[1];
+    fn ram_write_8(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
     }
 
     fn ram_read_8(&self, address: u16) -> u8 {
-        self.ram[self.selected_ram_bank as usize * 0x2000 + address as usize - 0xA000]
-    }
-
-    fn ram_read_16(&self, address: u16) -> u16 {
-        let n1 = self.read_8(address);
-        let n2 = self.read_8(address + 1);
-        u16::from_le_bytes([n1, n2])
+        self.ram_offset(address)
+            .map_or(0xFF, |offset| self.ram[offset])
     }
 }
 
@@ -250,46 +261,70 @@ impl Cartridge for CartridgeMBC1 {
         match address {
             0x0000..=0x1FFF => (),
             0x2000..=0x3FFF => self.select_rom_bank(value),
-            0x4000..=0x5FFF => self.select_ram_bank(value),
-            0x6000..=0x7FFF => unimplemented!(),
+            0x4000..=0x5FFF => self.select_upper_bank(value),
+            0x6000..=0x7FFF => self.select_banking_mode(value),
             0xA000..=0xBFFF => self.ram_write_8(address, value),
-            _ => unreachable!(),
-        }
-    }
-
-    fn write_16(&mut self, address: u16, value: u16) {
-        match address {
-            0xA000..=0xBFFF => self.ram_write_16(address, value),
-            _ => unreachable!(),
+            // Not decoded by the cartridge
+            _ => (),
         }
     }
 
     fn read_8(&self, address: u16) -> u8 {
+        let upper_bank = (self.upper_bank as usize) << 5;
         match address {
-            0x0000..=0x1FFF => self.rom[address as usize],
-            0x4000..=0x7FFF => self.rom_read_8(address),
+            0x0000..=0x3FFF => match self.ram_banking_mode {
+                true => self.rom_read_8(upper_bank, address),
+                false => self.rom_read_8(0, address),
+            },
+            0x4000..=0x7FFF => self.rom_read_8(upper_bank | self.rom_bank as usize, address),
             0xA000..=0xBFFF => self.ram_read_8(address),
-            _ => unreachable!(),
+            // Not decoded by the cartridge, the data bus is pulled up
+            _ => 0xFF,
         }
     }
 
-    fn read_16(&self, address: u16) -> u16 {
-        match address {
-            0x0000..=0x7FFF => self.rom_read_16(address),
-            0xA000..=0xBFFF => self.ram_read_16(address),
-            _ => unreachable!(),
+    fn dump_rom(&self) -> Vec<u8> {
+        self.rom.clone()
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_ram(&mut self, ram: &[u8]) {
+        if ram.len() != self.ram.len() {
+            error!(
+                "Cartridge RAM size mismatch: expected {:#X} bytes, got {:#X}",
+                self.ram.len(),
+                ram.len()
+            );
+            return;
         }
+        self.ram.copy_from_slice(ram);
     }
 
-    fn dump_rom(&self) -> Vec<u8> {
-        unimplemented!()
+    fn has_battery(&self) -> bool {
+        self.battery
     }
+}
 
-    fn dump_ram(&self) -> Vec<u8> {
-        unimplemented!()
+impl Snapshot for CartridgeMBC1 {
+    fn save(&self, state: &mut Writer) {
+        state.vec(&self.ram);
+        state.u8(self.rom_bank);
+        state.u8(self.upper_bank);
+        state.bool(self.ram_banking_mode);
     }
 
-    fn borrow_rom(&self) -> &[u8] {
-        unimplemented!()
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        let ram = state.vec()?;
+        if ram.len() != self.ram.len() {
+            return Err(save_state::Error::Invalid("cartridge RAM size mismatch"));
+        }
+        self.ram = ram;
+        self.select_rom_bank(state.u8()?);
+        self.select_upper_bank(state.u8()?);
+        self.ram_banking_mode = state.bool()?;
+        Ok(())
     }
 }
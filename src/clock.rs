@@ -1,71 +1,136 @@
-use std::time::{Duration, SystemTime};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
 
-use tracing::{error, warn};
+use crate::emulator::CYCLES_PER_SECOND;
 
-use crate::dmg::ClockTicks;
+/// Duration in T-cycles of the 4.194304 MHz system clock.
+pub type ClockTicks = usize;
 
-#[derive(Debug)]
-pub struct Clock {
-    last_stamp: SystemTime,
-    period_time: Duration,
+/// Once emulation falls this far behind real time, the lag is dropped instead of being caught
+/// up with a burst of frames.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// Real time over which the emulation speed is measured.
+const SPEED_SAMPLE: Duration = Duration::from_secs(1);
+
+/// What keeps the emulation running at the speed of the hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncSource {
+    /// Sleeps until real time catches up with emulated time.
+    Clock,
+    /// The frontend waits for display refreshes itself, the pacer only measures the speed.
+    VSync,
 }
 
-impl Clock {
-    pub fn new() -> Self {
-        Self {
-            last_stamp: SystemTime::now(),
-            period_time: Duration::from_nanos(238u64),
-        }
+/// How fast emulation runs relative to the hardware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    /// Emulated time runs this many times faster than real time.
+    Scaled(f64),
+    /// Runs as fast as the host allows.
+    Uncapped,
+}
+
+impl Default for Rate {
+    fn default() -> Self {
+        Rate::Scaled(1.0)
     }
+}
 
-    pub fn tick(&mut self) {
-        let elapsed = self.last_stamp.elapsed();
-        if let Err(err) = elapsed {
-            error!("{}", err.to_string());
-        } else {
-            let elapsed = elapsed.unwrap();
-            if elapsed > self.period_time {
-                // warn!("Last iteration took longer than 1 clock tick !");
-                self.last_stamp = SystemTime::now();
-                return;
-            }
+/// Paces emulation on a monotonic clock and measures its speed.
+///
+/// Deadlines are computed from the start of pacing rather than from the previous frame, so
+/// sleep inaccuracies do not add up over time. Rates other than full speed are always paced on
+/// the clock, as display refreshes come at the speed of the hardware.
+pub struct Pacer {
+    source: SyncSource,
+    rate: Rate,
+    /// Real time at which `emulated` started being counted.
+    epoch: Instant,
+    emulated: Duration,
+    sample_start: Instant,
+    sample_emulated: Duration,
+    speed: Option<f64>,
+}
+
+fn cycles_to_duration(cycles: ClockTicks) -> Duration {
+    Duration::from_nanos(cycles as u64 * 1_000_000_000 / CYCLES_PER_SECOND as u64)
+}
 
-            let to_sleep = self.period_time - elapsed;
-            std::thread::sleep(to_sleep);
-            self.last_stamp = SystemTime::now();
+impl Pacer {
+    pub fn new(source: SyncSource) -> Self {
+        let now = Instant::now();
+        Self {
+            source,
+            rate: Rate::default(),
+            epoch: now,
+            emulated: Duration::ZERO,
+            sample_start: now,
+            sample_emulated: Duration::ZERO,
+            speed: None,
         }
     }
-}
 
-pub struct TickCoordinator {
-    ticks_to_wait: isize,
-}
+    /// Whether the caller is to wait for display refreshes before every frame.
+    pub fn waits_for_vsync(&self) -> bool {
+        matches!(self.source, SyncSource::VSync) && self.rate == Rate::default()
+    }
 
-impl TickCoordinator {
-    pub fn new() -> Self {
-        Self { ticks_to_wait: 1 }
+    pub fn rate(&self) -> Rate {
+        self.rate
     }
 
-    pub fn tick(&mut self) -> bool {
-        self.ticks_to_wait -= 1;
-        self.ticks_to_wait <= 0
+    pub fn set_rate(&mut self, rate: Rate) {
+        self.rate = rate;
+        self.reset();
     }
 
-    pub fn ticks(&mut self, ticks: ClockTicks) -> bool {
-        self.ticks_to_wait -= ticks as isize;
-        self.ticks_to_wait <= 0
+    /// Restarts pacing from now, after the emulation was paused.
+    pub fn reset(&mut self) {
+        let now = Instant::now();
+        self.epoch = now;
+        self.emulated = Duration::ZERO;
+        self.sample_start = now;
+        self.sample_emulated = Duration::ZERO;
+    }
+
+    /// Accounts for `cycles` T-cycles of emulation and waits until they are due.
+    pub fn wait(&mut self, cycles: ClockTicks) {
+        let duration = cycles_to_duration(cycles);
+        self.sample_emulated += duration;
+
+        match (self.source, self.rate) {
+            (_, Rate::Uncapped) => (),
+            (SyncSource::VSync, Rate::Scaled(1.0)) => (),
+            (_, Rate::Scaled(multiplier)) => {
+                self.emulated += duration.div_f64(multiplier);
+                let deadline = self.epoch + self.emulated;
+                let now = Instant::now();
+                if deadline > now {
+                    thread::sleep(deadline - now);
+                } else if now - deadline > MAX_LAG {
+                    self.epoch = now - self.emulated;
+                }
+            }
+        }
+        self.measure();
     }
 
-    pub fn tick_all(&mut self) -> ClockTicks {
-        if self.ticks_to_wait <= 0 {
-            return 0;
+    fn measure(&mut self) {
+        let elapsed = self.sample_start.elapsed();
+        if elapsed < SPEED_SAMPLE {
+            return;
         }
-        let res = self.ticks_to_wait as ClockTicks;
-        self.ticks_to_wait = 0;
-        res
+        self.speed = Some(self.sample_emulated.as_secs_f64() / elapsed.as_secs_f64());
+        self.sample_start = Instant::now();
+        self.sample_emulated = Duration::ZERO;
     }
 
-    pub fn wait_for(&mut self, ticks: ClockTicks) {
-        self.ticks_to_wait = ticks as isize;
+    /// Returns the emulation speed relative to the hardware once per measurement, 1.0 being
+    /// full speed.
+    pub fn take_speed(&mut self) -> Option<f64> {
+        self.speed.take()
     }
 }
@@ -52,8 +52,7 @@ impl Instruction {
 }
 
 fn next_8(pc: u16, rom: &[u8]) -> u8 {
-    let res = rom[pc as usize];
-    res
+    rom[pc as usize]
 }
 
 fn next_16(pc: u16, rom: &[u8]) -> u16 {
@@ -0,0 +1,98 @@
+use crate::save_state::{self, Reader, Snapshot, Writer};
+
+/// OAM DMA controller.
+///
+/// A write to 0xFF46 starts a transfer after a one M-cycle startup delay. The transfer then
+/// copies one byte per M-cycle, 0xA0 bytes in total. Writing 0xFF46 while a transfer is
+/// running restarts it, the old transfer keeps going until the new one has started.
+#[derive(Debug, Default)]
+pub struct OamDma {
+    /// Source address of a requested transfer and the M-cycles left before it starts.
+    pending: Option<(u16, u8)>,
+    /// Source address and index of the next byte of the running transfer.
+    active: Option<(u16, u16)>,
+    /// Last byte read by the transfer, seen by the CPU on bus conflicts.
+    last_value: u8,
+}
+
+const TRANSFER_LENGTH: u16 = 0xA0;
+const STARTUP_DELAY: u8 = 1;
+
+impl OamDma {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&mut self, source: u8) {
+        let mut source = (source as u16) << 8;
+        // Sources past WRAM see the echo RAM mirror instead of OAM and I/O
+        if source >= 0xE000 {
+            source -= 0x2000;
+        }
+        self.pending = Some((source, STARTUP_DELAY));
+    }
+
+    /// Source address of the running transfer, used to find out which bus it occupies.
+    pub fn source(&self) -> Option<u16> {
+        self.active.map(|(source, _)| source)
+    }
+
+    pub fn last_value(&self) -> u8 {
+        self.last_value
+    }
+
+    pub fn set_last_value(&mut self, value: u8) {
+        self.last_value = value;
+    }
+
+    /// Advances the controller by one M-cycle.
+    ///
+    /// Returns the source address and the OAM offset of the byte to copy during this cycle.
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        let transfer = self.active.map(|(source, index)| {
+            self.active = (index + 1 < TRANSFER_LENGTH).then_some((source, index + 1));
+            (source + index, index)
+        });
+
+        if let Some((source, delay)) = self.pending {
+            if delay <= 1 {
+                self.pending = None;
+                self.active = Some((source, 0));
+            } else {
+                self.pending = Some((source, delay - 1));
+            }
+        }
+
+        transfer
+    }
+}
+
+impl Snapshot for OamDma {
+    fn save(&self, state: &mut Writer) {
+        let (pending_source, delay) = self.pending.unwrap_or_default();
+        state.bool(self.pending.is_some());
+        state.u16(pending_source);
+        state.u8(delay);
+        let (active_source, index) = self.active.unwrap_or_default();
+        state.bool(self.active.is_some());
+        state.u16(active_source);
+        state.u16(index);
+        state.u8(self.last_value);
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        let pending = state.bool()?;
+        let pending_source = state.u16()?;
+        let delay = state.u8()?;
+        self.pending = pending.then_some((pending_source, delay));
+        let active = state.bool()?;
+        let active_source = state.u16()?;
+        let index = state.u16()?;
+        if index >= TRANSFER_LENGTH {
+            return Err(save_state::Error::Invalid("OAM DMA index out of range"));
+        }
+        self.active = active.then_some((active_source, index));
+        self.last_value = state.u8()?;
+        Ok(())
+    }
+}
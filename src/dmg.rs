@@ -1,148 +1,684 @@
 use std::{
-    cell::RefCell,
-    io::Write,
-    rc::Rc,
-    sync::mpsc::{Receiver, Sender},
+    fs,
+    io::{ErrorKind, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use tracing::error;
+use dmg_rs::{
+    boot_rom::BootRom,
+    clock::{Pacer, Rate, SyncSource},
+    emulator::{CYCLES_PER_FRAME, CYCLES_PER_SECOND},
+    error::Fault,
+    hash_log::{self, FrameHashes, HashLog},
+    model::Model,
+    movie::{Input, Movie, Start},
+    ppu::PixelBuffer,
+    rewind::Rewind,
+    save_state::Header,
+    Emulator, FrameOutput,
+};
+use tracing::{error, info};
 
 use crate::{
-    cartridge,
-    clock::TickCoordinator,
-    joypad::Joypad,
-    lr35902::{JOYPADBIT, LR35902},
-    mmu::MemoryMapUnit,
-    ppu::PixelProcessingUnit,
-    thread::{DmgMessage, GuiMessage},
+    thread::{DmgMessage, GuiMessage, MovieStart, MovieStatus},
+    watcher::RomWatcher,
 };
 
+/// Save state slots, numbered from 1.
+pub const SAVE_STATE_SLOTS: usize = 4;
+
+/// Movie being recorded or played back.
+struct MovieSession {
+    movie: Movie,
+    /// Next frame to record or play.
+    frame: usize,
+    /// Plays the recorded input back while set, records over it from the current frame
+    /// otherwise.
+    read_only: bool,
+    /// Hashes of the machine after every frame of the movie, saved with it for `--verify`.
+    hashes: HashLog,
+    /// Set while `hashes` is the log loaded from disk. Playback does not hash over it and it is
+    /// not saved again, so that it stays the reference of the build that recorded the movie.
+    log_loaded: bool,
+}
+
+/// Desktop frontend side of the emulator, running on its own thread and talking to the GUI
+/// over channels.
 pub struct DotMatrixGame {
-    mmu: Rc<RefCell<MemoryMapUnit>>,
-    cpu: LR35902,
-    ppu: PixelProcessingUnit,
-    joypad: Rc<RefCell<Joypad>>,
+    emulator: Emulator,
     tx: Sender<DmgMessage>,
     rx: Receiver<GuiMessage>,
     step_mode: bool,
     next_step: bool,
+    /// Set to run a single frame while in step mode.
+    next_frame: bool,
     step_count: usize,
+    rom_path: String,
+    watcher: Option<RomWatcher>,
+    /// Save state slot loaded after the ROM is reloaded from disk.
+    watch_restore: Option<usize>,
+    pacer: Pacer,
+    /// State of every frame, newest last.
+    rewind: Rewind,
+    /// Set while the game is played backwards.
+    rewinding: bool,
+    /// Set when the GUI presented a frame since the last vsync wait or turbo frame.
+    vsync: bool,
+    /// Buttons held in the GUI. While a movie is active, they only reach the emulator through
+    /// the movie, at the start of a frame.
+    held: Input,
+    movie: Option<MovieSession>,
+    movie_path: String,
+    /// Cleared once a movie replaced the cartridge RAM, so that it does not overwrite the save
+    /// file of the game.
+    save_battery_ram: bool,
 }
 
-pub type ClockTicks = usize;
+/// Movies are recorded next to the ROM by default, `game.gb` to `game.movie`.
+fn default_movie_path(rom_path: &str) -> String {
+    Path::new(rom_path)
+        .with_extension("movie")
+        .to_string_lossy()
+        .into_owned()
+}
 
 impl DotMatrixGame {
+    /// Without a boot ROM, the game starts at 0x0100 as if the boot ROM had just run.
     pub fn new_with_rom_path(
         path: &str,
+        model: Model,
+        boot_rom: Option<BootRom>,
         tx: Sender<DmgMessage>,
         rx: Receiver<GuiMessage>,
     ) -> anyhow::Result<Self> {
-        let cartridge = cartridge::from_file(path)?;
-        let joypad = Rc::new(RefCell::new(Joypad::new()));
-        let mmu = Rc::new(RefCell::new(MemoryMapUnit::new(cartridge, joypad.clone())));
-        let ppu = PixelProcessingUnit::new(mmu.clone(), tx.clone());
-        let cpu = LR35902::new(mmu.clone());
-
-        Ok(Self {
-            mmu: mmu.clone(),
-            cpu,
-            ppu,
-            joypad,
+        let mut emulator = Emulator::new(model, boot_rom);
+        emulator.load_rom(path)?;
+
+        let mut dmg = Self {
+            emulator,
             tx,
             rx,
             step_mode: false,
             next_step: false,
+            next_frame: false,
             step_count: 0,
-        })
+            rom_path: path.to_owned(),
+            watcher: None,
+            watch_restore: None,
+            pacer: Pacer::new(SyncSource::Clock),
+            rewind: Rewind::new(0),
+            rewinding: false,
+            vsync: false,
+            held: Input::default(),
+            movie: None,
+            movie_path: default_movie_path(path),
+            save_battery_ram: true,
+        };
+        dmg.load_battery_ram();
+        Ok(dmg)
+    }
+
+    /// Logs CPU accesses to VRAM and OAM while the PPU has them locked.
+    pub fn set_log_illegal_access(&mut self, enabled: bool) {
+        self.emulator.set_log_illegal_access(enabled);
+    }
+
+    /// Reloads the ROM and resets the machine whenever the ROM file changes on disk.
+    pub fn set_watch_rom(&mut self, watch: bool) {
+        self.watcher = watch.then(|| RomWatcher::new(&self.rom_path));
+    }
+
+    /// Loads the save state in `slot` after the ROM is reloaded from disk, to get back to where
+    /// the game was. It only loads as long as the ROM is unchanged from when it was saved.
+    pub fn set_watch_restore(&mut self, slot: Option<usize>) {
+        self.watch_restore = slot;
+    }
+
+    /// Selects what paces the emulation, the system clock by default.
+    pub fn set_sync(&mut self, source: SyncSource) {
+        self.pacer = Pacer::new(source);
+    }
+
+    /// Keeps the given emulated seconds of states to rewind through, none when 0.
+    pub fn set_rewind_depth(&mut self, seconds: usize) {
+        self.rewind = Rewind::new(seconds * CYCLES_PER_SECOND / CYCLES_PER_FRAME);
+    }
+
+    /// Where movies get recorded, next to the ROM by default.
+    pub fn set_movie_path(&mut self, path: &str) {
+        self.movie_path = path.to_owned();
+    }
+
+    /// Plays the movie at the movie path back from its start.
+    pub fn play_movie(&mut self) -> anyhow::Result<()> {
+        let movie = Movie::from_file(&self.movie_path)?;
+        // Without a log, playback writes one. An existing log is only replaced once recording
+        // changes the movie
+        let log_path = hash_log::log_path(&self.movie_path);
+        let log_loaded = log_path.exists();
+        let hashes = match log_loaded {
+            true => HashLog::from_file(&log_path.to_string_lossy())?,
+            false => HashLog::default(),
+        };
+        self.flush_battery_ram();
+        movie.start(&mut self.emulator)?;
+        self.save_battery_ram = false;
+        info!(
+            "Playing movie {}, {} frames",
+            self.movie_path,
+            movie.frames.len()
+        );
+        self.rewind.clear();
+        self.movie = Some(MovieSession {
+            movie,
+            frame: 0,
+            read_only: true,
+            hashes,
+            log_loaded,
+        });
+        Ok(())
+    }
+
+    /// Starts recording a movie to the movie path, replacing the active one.
+    pub fn record_movie(&mut self, start: MovieStart) {
+        self.stop_movie();
+        let start = match start {
+            MovieStart::PowerOn => {
+                self.flush_battery_ram();
+                self.save_battery_ram = false;
+                self.emulator.power_cycle();
+                Start::PowerOn
+            }
+            MovieStart::CurrentState => Start::SaveState(self.emulator.save_state()),
+        };
+        info!("Recording movie {}", self.movie_path);
+        self.rewind.clear();
+        self.movie = Some(MovieSession {
+            movie: Movie::new(&self.emulator, start),
+            frame: 0,
+            read_only: false,
+            hashes: HashLog::default(),
+            log_loaded: false,
+        });
+    }
+
+    fn toggle_movie_read_only(&mut self) {
+        if let Some(session) = &mut self.movie {
+            session.read_only = !session.read_only;
+            info!(
+                "Movie {} at frame {}",
+                match session.read_only {
+                    true => "read-only",
+                    false => "recording",
+                },
+                session.frame
+            );
+        }
+        self.save_movie();
+    }
+
+    /// Saves the movie, and its hash log once every frame of it was hashed. A log loaded from
+    /// disk is left as it is.
+    fn save_movie(&self) {
+        let Some(session) = &self.movie else {
+            return;
+        };
+        if let Err(err) = session.movie.save(&self.movie_path) {
+            error!("Could not save movie {}: {}", self.movie_path, err);
+        }
+        if !session.log_loaded && session.hashes.frames.len() == session.movie.frames.len() {
+            let path = hash_log::log_path(&self.movie_path);
+            if let Err(err) = session.hashes.save(&path.to_string_lossy()) {
+                error!("Could not save hash log {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    fn stop_movie(&mut self) {
+        self.save_movie();
+        if self.movie.take().is_some() {
+            info!("Movie stopped");
+            self.emulator.set_buttons(&self.held.buttons());
+            self.send_movie_status();
+        }
+    }
+
+    fn send_movie_status(&mut self) {
+        let movie = self.movie.as_ref().map(|session| MovieStatus {
+            frame: session.frame,
+            length: session.movie.frames.len(),
+            read_only: session.read_only,
+        });
+        if let Err(err) = self.tx.send(DmgMessage::Movie(movie)) {
+            error!("Could not send Movie Message: {:?}", err);
+        }
+    }
+
+    /// Runs a frame with the buttons the movie has for it, or records the held ones, and hashes
+    /// the machine after the frames of the movie unless they are played back over a loaded log.
+    ///
+    /// Past the end of a read-only movie, the buttons held in the GUI are used again.
+    fn run_frame(&mut self) -> FrameOutput {
+        if let Some(session) = &mut self.movie {
+            let recorded = session.movie.frames.get(session.frame).copied();
+            let input = match (session.read_only, recorded) {
+                (true, Some(input)) => input,
+                (true, None) => self.held,
+                (false, _) => {
+                    session.movie.frames.truncate(session.frame);
+                    session.movie.frames.push(self.held);
+                    session.hashes.frames.truncate(session.frame);
+                    session.log_loaded = false;
+                    self.held
+                }
+            };
+            if session.read_only && session.frame == session.movie.frames.len() {
+                info!("Movie finished");
+            }
+            session.frame += 1;
+            self.emulator.set_buttons(&input.buttons());
+        }
+        let output = self.emulator.run_frame();
+
+        if let Some(session) = &mut self.movie {
+            let hashed = !session.read_only || !session.log_loaded;
+            if hashed && session.frame <= session.movie.frames.len() {
+                match FrameHashes::of(&self.emulator) {
+                    Ok(hashes) => session.hashes.set(session.frame, hashes),
+                    Err(err) => error!("Could not hash frame {}: {}", session.frame, err),
+                }
+            }
+        }
+        output
+    }
+
+    /// Battery backed RAM is saved next to the ROM, `game.gb` having it in `game.sav`.
+    fn battery_ram_path(&self) -> PathBuf {
+        Path::new(&self.rom_path).with_extension("sav")
+    }
+
+    fn load_battery_ram(&mut self) {
+        if self.emulator.battery_ram().is_none() {
+            return;
+        }
+        let path = self.battery_ram_path();
+        match fs::read(&path) {
+            Ok(ram) => {
+                info!("Loaded battery RAM from {}", path.display());
+                self.emulator.load_battery_ram(&ram);
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => error!("Could not load battery RAM {}: {}", path.display(), err),
+        }
+    }
+
+    fn flush_battery_ram(&self) {
+        if !self.save_battery_ram {
+            return;
+        }
+        let Some(ram) = self.emulator.battery_ram() else {
+            return;
+        };
+        let path = self.battery_ram_path();
+        if let Err(err) = fs::write(&path, ram) {
+            error!("Could not save battery RAM to {}: {}", path.display(), err);
+        }
+    }
+
+    /// Tells the GUI the machine starts over and shows its screen, even while paused.
+    fn report_reset(&mut self) {
+        self.pacer.reset();
+        if let Err(err) = self.tx.send(DmgMessage::Reset) {
+            error!("Could not send Reset Message: {:?}", err);
+        }
+        let screen = Box::new(*self.emulator.screen());
+        self.send_frame(Some(screen));
+    }
+
+    /// Resets the machine, which stops the active movie as it cannot record resets.
+    fn reset(&mut self) {
+        self.stop_movie();
+        self.emulator.reset();
+        info!("Reset");
+        self.report_reset();
+    }
+
+    /// Power cycles the machine. Battery backed RAM keeps its content, like it does on the
+    /// hardware.
+    fn power_cycle(&mut self, randomize_ram: bool) {
+        self.stop_movie();
+        let battery_ram = self.emulator.battery_ram();
+        self.emulator.power_cycle();
+        if randomize_ram {
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64);
+            self.emulator.randomize_ram(seed);
+        }
+        if let Some(ram) = battery_ram {
+            self.emulator.load_battery_ram(&ram);
+        }
+        info!("Power cycled");
+        self.report_reset();
+    }
+
+    /// Swaps the cartridge for the ROM at `path`, saving the battery RAM of the current one
+    /// first. The current game keeps running if the ROM cannot be loaded.
+    fn load_rom(&mut self, path: &str) {
+        self.flush_battery_ram();
+        if let Err(err) = self.emulator.load_rom(path) {
+            error!("Could not load ROM {}: {}", path, err);
+            return;
+        }
+
+        info!("Loaded ROM {}", path);
+        self.stop_movie();
+        self.rom_path = path.to_owned();
+        self.movie_path = default_movie_path(path);
+        self.save_battery_ram = true;
+        if self.watcher.is_some() {
+            self.watcher = Some(RomWatcher::new(path));
+        }
+        self.rewind.clear();
+        self.load_battery_ram();
+        if let Err(err) = self.tx.send(DmgMessage::RomLoaded) {
+            error!("Could not send ROM Loaded Message: {:?}", err);
+        }
+        self.send_thumbnails();
+        self.report_reset();
+    }
+
+    /// Reloads the ROM once it changed on disk. The active movie is stopped, as its frames were
+    /// recorded with the previous ROM.
+    fn check_rom_changed(&mut self) {
+        let changed = match self.watcher {
+            Some(ref mut watcher) => watcher.poll(),
+            None => false,
+        };
+        if !changed {
+            return;
+        }
+
+        let battery_ram = self.emulator.battery_ram();
+        match self.emulator.load_rom(&self.rom_path) {
+            Ok(()) => {
+                info!("ROM changed on disk, reloading {}", self.rom_path);
+                self.stop_movie();
+                self.rewind.clear();
+                if let Some(ram) = battery_ram {
+                    self.emulator.load_battery_ram(&ram);
+                }
+                self.report_reset();
+                if let Some(slot) = self.watch_restore {
+                    self.load_state(slot);
+                }
+            }
+            Err(err) => error!("Could not reload ROM {}: {}", self.rom_path, err),
+        }
     }
 
     fn handle_gui_messages(&mut self) -> bool {
         while let Ok(message) = self.rx.try_recv() {
-            match message {
-                GuiMessage::Close => return false,
-                GuiMessage::NextInstruction(count) => {
-                    self.next_step = true;
-                    self.step_count = count
+            if !self.handle_gui_message(message) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns false once the GUI is closing.
+    fn handle_gui_message(&mut self, message: GuiMessage) -> bool {
+        match message {
+            GuiMessage::Close => return false,
+            GuiMessage::NextInstruction(count) => {
+                self.next_step = true;
+                self.step_count = count
+            }
+            GuiMessage::RequestState => self.send_state_messages(),
+            GuiMessage::StepMode(mode) => {
+                if self.step_mode && !mode {
+                    self.pacer.reset();
                 }
-                GuiMessage::RequestState => self.send_state_messages(),
-                GuiMessage::StepMode(mode) => self.step_mode = mode,
-                GuiMessage::ButtonPressed(button) => {
-                    self.joypad.borrow_mut().button_pressed(button);
-                    let value = self.mmu.borrow().read_8(0xFF0F); // Trigger Interrupt ?
-                    self.mmu.borrow_mut().write_8(0xFF0F, value | JOYPADBIT);
+                self.step_mode = mode
+            }
+            GuiMessage::ButtonPressed(button) => {
+                self.held.set(button, true);
+                if self.movie.is_none() {
+                    self.emulator.press_button(button);
                 }
-                GuiMessage::ButtonReleased(button) => {
-                    self.joypad.borrow_mut().button_released(button)
+            }
+            GuiMessage::ButtonReleased(button) => {
+                self.held.set(button, false);
+                if self.movie.is_none() {
+                    self.emulator.release_button(button);
                 }
+            }
+            GuiMessage::VSync => self.vsync = true,
+            GuiMessage::Rate(rate) => self.pacer.set_rate(rate),
+            GuiMessage::NextFrame => self.next_frame = true,
+            // Going back would get the movie out of sync with its frames
+            GuiMessage::Rewind(rewinding) => self.rewinding = rewinding && self.movie.is_none(),
+            GuiMessage::RecordMovie(start) => self.record_movie(start),
+            GuiMessage::ToggleMovieReadOnly => self.toggle_movie_read_only(),
+            GuiMessage::StopMovie => self.stop_movie(),
+            GuiMessage::SaveState(slot) => self.save_state(slot),
+            GuiMessage::LoadState(slot) => self.load_state(slot),
+            GuiMessage::Reset => self.reset(),
+            GuiMessage::PowerCycle { randomize_ram } => self.power_cycle(randomize_ram),
+            GuiMessage::LoadRom(path) => self.load_rom(&path),
+        };
+        true
+    }
+
+    /// Blocks until the GUI presents a frame, handling the other messages meanwhile.
+    ///
+    /// Returns false once the GUI is closing.
+    fn wait_vsync(&mut self) -> bool {
+        while !self.vsync && !self.step_mode {
+            let running = match self.rx.recv() {
+                Ok(message) => self.handle_gui_message(message),
+                Err(_) => false,
             };
+            if !running {
+                return false;
+            }
         }
+        self.vsync = false;
         true
     }
 
     fn send_state_messages(&mut self) {
-        let registers_copy = self.cpu.registers.clone();
-        if let Err(_) = self.tx.send(DmgMessage::RegistersStatus(registers_copy)) {
-            error!("Could not send Registers Message !");
+        let registers_copy = self.emulator.registers().clone();
+        if let Err(err) = self.tx.send(DmgMessage::RegistersStatus(registers_copy)) {
+            error!("Could not send Registers Message: {:?}", err);
+        }
+
+        let interrupts = self.emulator.interrupt_state();
+        if let Err(err) = self.tx.send(DmgMessage::InterruptState(interrupts)) {
+            error!("Could not send Interrupt Message: {:?}", err);
+        }
+
+        let memory = self.emulator.memory_dump();
+        if let Err(err) = self.tx.send(DmgMessage::MemoryState(memory)) {
+            error!("Could not send Memory Message: {:?}", err);
+        }
+
+        self.send_movie_status();
+    }
+
+    /// Pauses on a fault, so that the state it happened in can be inspected.
+    fn report_fault(&mut self, fault: Option<Fault>) {
+        if let Some(fault) = fault {
+            info!("Paused on fault: {}", fault);
+            self.step_mode = true;
+            if let Err(err) = self.tx.send(DmgMessage::Fault(fault)) {
+                error!("Could not send Fault Message: {:?}", err);
+            }
+        }
+    }
+
+    fn send_frame(&mut self, frame: Option<Box<PixelBuffer>>) {
+        if let Some(frame) = frame {
+            if let Err(err) = self.tx.send(DmgMessage::Render(Arc::from(frame))) {
+                error!("Could not send Pixel buffer to GUI: {:?}", err);
+            }
+        }
+    }
+
+    /// Save states sit next to the ROM, `game.gb` having its slots in `game.ss1` and so on.
+    fn save_state_path(&self, slot: usize) -> PathBuf {
+        Path::new(&self.rom_path).with_extension(format!("ss{}", slot))
+    }
+
+    fn save_state(&mut self, slot: usize) {
+        let path = self.save_state_path(slot);
+        let state = self.emulator.save_state();
+        match fs::write(&path, &state) {
+            Ok(()) => {
+                info!("Saved state to {}", path.display());
+                self.send_thumbnail(slot, &state);
+            }
+            Err(err) => error!("Could not save state to {}: {}", path.display(), err),
+        }
+    }
+
+    /// Loads the state in `slot`, which stops the active movie as its frames no longer follow
+    /// from the loaded state.
+    fn load_state(&mut self, slot: usize) {
+        let path = self.save_state_path(slot);
+        let result = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|state| Ok(self.emulator.load_state(&state)?));
+        match result {
+            Ok(()) => {
+                info!("Loaded state from {}", path.display());
+                self.stop_movie();
+                self.pacer.reset();
+                // Shows the state right away, even while paused
+                let screen = Box::new(*self.emulator.screen());
+                self.send_frame(Some(screen));
+            }
+            Err(err) => error!("Could not load state from {}: {}", path.display(), err),
+        }
+    }
+
+    fn send_thumbnail(&mut self, slot: usize, state: &[u8]) {
+        let thumbnail = match Header::read(state) {
+            Ok(header) => header.thumbnail,
+            Err(err) => {
+                error!("Could not read save state slot {}: {}", slot, err);
+                return;
+            }
+        };
+        let message = DmgMessage::SaveStateThumbnail(slot, Arc::from(thumbnail));
+        if let Err(err) = self.tx.send(message) {
+            error!("Could not send Thumbnail Message: {:?}", err);
         }
+    }
 
-        let memory = self.mmu.borrow().get_memory_dump();
-        if let Err(_) = self.tx.send(DmgMessage::MemoryState(memory)) {
-            error!("Could not send Memory Message !");
+    fn send_thumbnails(&mut self) {
+        for slot in 1..=SAVE_STATE_SLOTS {
+            if let Ok(state) = fs::read(self.save_state_path(slot)) {
+                self.send_thumbnail(slot, &state);
+            }
+        }
+    }
+
+    /// Goes back one frame, or stays on the oldest one once the buffer is empty.
+    fn rewind_frame(&mut self) {
+        let Some(state) = self.rewind.pop() else {
+            return;
+        };
+        match self.emulator.load_snapshot(&state) {
+            Ok(()) => {
+                let screen = Box::new(*self.emulator.screen());
+                self.send_frame(Some(screen));
+            }
+            Err(err) => error!("Could not rewind: {}", err),
+        }
+    }
+
+    fn report_speed(&mut self) {
+        if let Some(speed) = self.pacer.take_speed() {
+            if let Err(err) = self.tx.send(DmgMessage::Speed(speed)) {
+                error!("Could not send Speed Message: {:?}", err);
+            }
         }
     }
 
     pub fn start_game(&mut self) -> anyhow::Result<()> {
-        let mut cpu_ticks = TickCoordinator::new();
-        let mut ppu_ticks = TickCoordinator::new();
+        self.send_thumbnails();
         loop {
             // let _ = tick_span.enter();
-            if let false = self.handle_gui_messages() {
+            if !self.handle_gui_messages() {
                 break;
             }
+            self.check_rom_changed();
 
-            std::thread::sleep(std::time::Duration::from_millis(16));
+            if self.pacer.waits_for_vsync() && !self.wait_vsync() {
+                break;
+            }
 
-            if !self.step_mode {
+            if !self.step_mode && self.rewinding {
+                // Frames are loaded rather than emulated, which keeps the sound muted
+                self.rewind_frame();
+                self.pacer.wait(CYCLES_PER_FRAME);
+                self.report_speed();
+            } else if !self.step_mode {
                 // Normal execution flow
-                for _ in 0..69905 {
-                    self.mmu.borrow_mut().timer_tick();
-                    if cpu_ticks.tick() {
-                        let ticks = self.cpu.step();
-                        cpu_ticks.wait_for(ticks);
-                    }
-
-                    if ppu_ticks.tick() {
-                        let ticks = self.ppu.step();
-                        ppu_ticks.wait_for(ticks);
-                    }
+                let output = self.run_frame();
+                // In turbo, frames are only sent as fast as the GUI presents them
+                if self.pacer.rate() != Rate::Uncapped || mem::take(&mut self.vsync) {
+                    self.send_frame(output.frame);
+                }
+                self.report_fault(output.fault);
+                if self.rewind.depth() > 0 {
+                    self.rewind.push(self.emulator.snapshot());
                 }
+                self.pacer.wait(output.cycles);
+                self.report_speed();
+            } else if self.next_frame {
+                // Frame advance while paused
+                let output = self.run_frame();
+                self.send_frame(output.frame);
+                self.report_fault(output.fault);
+
+                self.next_frame = false;
             } else {
                 // Step mode execution flow
                 if !self.next_step {
+                    std::thread::sleep(Duration::from_millis(16));
                     continue;
                 }
 
                 while self.step_count > 0 {
-                    self.mmu.borrow_mut().timer_tick();
-                    let ct = cpu_ticks.tick_all();
-                    let ticks = self.cpu.step();
-                    cpu_ticks.wait_for(ticks);
-
-                    if ppu_ticks.ticks(ct) {
-                        let ticks = self.ppu.step();
-                        ppu_ticks.wait_for(ticks);
-                    }
+                    self.emulator.step_instruction();
                     self.step_count -= 1;
                 }
+                let frame = self.emulator.take_frame();
+                self.send_frame(frame);
+                let fault = self.emulator.take_fault();
+                self.report_fault(fault);
 
                 self.next_step = false;
             }
         }
 
-        if let Some(ref tracer) = self.cpu.tracer {
+        self.save_movie();
+        self.flush_battery_ram();
+        if let Some(tracer) = self.emulator.tracer() {
             let mut file = std::fs::File::create("dump.trace")?;
             file.write_all(&tracer.to_string().into_bytes())?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests;
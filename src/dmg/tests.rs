@@ -0,0 +1,99 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::channel,
+};
+
+use dmg_rs::{
+    hash_log::{self, FrameHashes, HashLog},
+    model::Model,
+    movie::{Input, Movie, Start},
+    save_state::Section,
+    Emulator,
+};
+
+use super::DotMatrixGame;
+use crate::verify;
+
+const FRAMES: usize = 5;
+
+/// Directory of its own for every test, removed first in case a previous run failed.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dmg-rs-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes a ROM that counts in A forever and a movie of it, and returns their paths.
+fn write_movie(dir: &Path) -> (String, String) {
+    let mut rom = vec![0; 0x8000];
+    // INC A; LD (0xC000), A; JR -6
+    rom[0x0100..0x0106].copy_from_slice(&[0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xFA]);
+    let rom_path = dir.join("game.gb");
+    fs::write(&rom_path, &rom).unwrap();
+
+    let mut emulator = Emulator::new(Model::Dmg, None);
+    emulator.load_rom_data(rom).unwrap();
+    let mut movie = Movie::new(&emulator, Start::PowerOn);
+    movie.frames = vec![Input::default(); FRAMES];
+    let movie_path = dir.join("game.movie");
+    movie.save(&movie_path.to_string_lossy()).unwrap();
+
+    (
+        rom_path.to_string_lossy().into_owned(),
+        movie_path.to_string_lossy().into_owned(),
+    )
+}
+
+/// Plays the movie in the frontend past its end, then stops it.
+fn play_movie(rom_path: &str, movie_path: &str) {
+    let (tx, _dmg_rx) = channel();
+    let (_gui_tx, rx) = channel();
+    let mut dmg = DotMatrixGame::new_with_rom_path(rom_path, Model::Dmg, None, tx, rx).unwrap();
+    dmg.set_movie_path(movie_path);
+    dmg.play_movie().unwrap();
+    for _ in 0..FRAMES + 2 {
+        dmg.run_frame();
+    }
+    dmg.stop_movie();
+}
+
+#[test]
+fn playback_keeps_the_loaded_hash_log() {
+    let dir = test_dir("keeps-log");
+    let (rom_path, movie_path) = write_movie(&dir);
+    // A log the current build does not match, as after a determinism regression
+    let log = HashLog {
+        frames: vec![
+            FrameHashes {
+                screen: 0,
+                sections: [0; Section::ALL.len()],
+            };
+            FRAMES
+        ],
+    };
+    let log_path = hash_log::log_path(&movie_path);
+    log.save(&log_path.to_string_lossy()).unwrap();
+    let expected = fs::read(&log_path).unwrap();
+
+    play_movie(&rom_path, &movie_path);
+
+    assert_eq!(fs::read(&log_path).unwrap(), expected);
+    assert!(!verify::verify(&rom_path, Model::Dmg, None, &movie_path).unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn playback_writes_a_missing_hash_log() {
+    let dir = test_dir("writes-log");
+    let (rom_path, movie_path) = write_movie(&dir);
+
+    play_movie(&rom_path, &movie_path);
+
+    let log = HashLog::from_file(&hash_log::log_path(&movie_path).to_string_lossy()).unwrap();
+    assert_eq!(log.frames.len(), FRAMES);
+    assert!(verify::verify(&rom_path, Model::Dmg, None, &movie_path).unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
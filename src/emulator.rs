@@ -0,0 +1,322 @@
+use std::{fs, sync::Arc};
+
+use crate::{
+    boot_rom::BootRom,
+    bus::Bus,
+    cartridge::{self, Cartridge},
+    clock::ClockTicks,
+    error::Fault,
+    interrupt::InterruptState,
+    joypad::DmgButton,
+    lr35902::{Registers, LR35902},
+    mmu::MemoryMapUnit,
+    model::Model,
+    ppu::PixelBuffer,
+    save_state::{self, Header, SaveState, Section, Sections, Snapshot, Writer},
+    tracer::Tracer,
+};
+
+/// T-cycles the PPU takes to draw a frame, LCD on or off.
+pub const CYCLES_PER_FRAME: ClockTicks = 70224;
+
+/// T-cycles in one second of emulated time.
+pub const CYCLES_PER_SECOND: ClockTicks = 4_194_304;
+
+/// What happened during one call to `Emulator::run_frame`.
+#[derive(Debug)]
+pub struct FrameOutput {
+    /// Frame completed by the PPU, if it finished one. The PPU does not stop while the LCD is
+    /// off, so frames keep coming then.
+    pub frame: Option<Box<PixelBuffer>>,
+    /// T-cycles actually run, instructions are not split at the frame boundary.
+    pub cycles: ClockTicks,
+    /// Set when the CPU locked up during the frame.
+    pub fault: Option<Fault>,
+}
+
+/// A Game Boy, without any frontend.
+///
+/// The machine is driven by the caller one frame or one instruction at a time, nothing runs in
+/// the background and no real time pacing is applied.
+#[derive(Debug)]
+pub struct Emulator {
+    mmu: MemoryMapUnit,
+    cpu: LR35902,
+    model: Model,
+    boot_rom: Option<BootRom>,
+    /// ROM of the inserted cartridge, kept to power the machine on again.
+    rom: Option<Vec<u8>>,
+    /// Hash of `rom`, save states only load on the ROM they were taken with.
+    rom_hash: u32,
+    /// T-cycles the last frame ran past its end, taken off the next one.
+    overshoot: ClockTicks,
+    log_illegal_access: bool,
+}
+
+impl Emulator {
+    /// Creates a machine with an empty cartridge slot.
+    ///
+    /// Without a boot ROM, a loaded game starts at 0x0100 as if the boot ROM had just run.
+    pub fn new(model: Model, boot_rom: Option<BootRom>) -> Self {
+        let (mmu, cpu) = Self::power_on(cartridge::empty(), model, boot_rom.clone());
+        Self {
+            mmu,
+            cpu,
+            model,
+            boot_rom,
+            rom: None,
+            rom_hash: save_state::rom_hash(&[]),
+            overshoot: 0,
+            log_illegal_access: false,
+        }
+    }
+
+    fn power_on(
+        cartridge: Box<dyn Cartridge>,
+        model: Model,
+        boot_rom: Option<BootRom>,
+    ) -> (MemoryMapUnit, LR35902) {
+        let skip_boot = boot_rom.is_none();
+        let mmu = MemoryMapUnit::new(cartridge, model, boot_rom);
+        let mut cpu = LR35902::new();
+        if skip_boot {
+            cpu.skip_boot(model, &mmu);
+        }
+        (mmu, cpu)
+    }
+
+    /// Inserts the ROM at `path` and powers the machine on.
+    pub fn load_rom(&mut self, path: &str) -> cartridge::Result<()> {
+        let rom = fs::read(path).map_err(cartridge::Error::Loading)?;
+        self.load_rom_data(rom)
+    }
+
+    /// Inserts a ROM image and powers the machine on.
+    pub fn load_rom_data(&mut self, rom: Vec<u8>) -> cartridge::Result<()> {
+        let cartridge = cartridge::from_rom(rom.clone())?;
+        self.rom_hash = save_state::rom_hash(&rom);
+        self.rom = Some(rom);
+        self.insert(cartridge);
+        Ok(())
+    }
+
+    fn insert(&mut self, cartridge: Box<dyn Cartridge>) {
+        (self.mmu, self.cpu) = Self::power_on(cartridge, self.model, self.boot_rom.clone());
+        self.mmu.set_log_illegal_access(self.log_illegal_access);
+        self.overshoot = 0;
+    }
+
+    /// Resets the machine, running the boot ROM again or skipping it. Work RAM and battery
+    /// backed cartridge RAM keep their content.
+    pub fn reset(&mut self) {
+        let wram = *self.mmu.wram();
+        let battery_ram = self.mmu.battery_ram();
+        self.power_cycle();
+        self.mmu.load_wram(&wram);
+        if let Some(ram) = battery_ram {
+            self.mmu.load_battery_ram(&ram);
+        }
+    }
+
+    /// Fills work RAM and high RAM with pseudo random values from `seed`, like the hardware
+    /// powers on with. Meant to be called right after powering on, to catch games reading
+    /// memory they never initialized.
+    pub fn randomize_ram(&mut self, seed: u64) {
+        self.mmu.randomize_ram(seed);
+    }
+
+    /// Power cycles the machine with a fresh copy of the cartridge, battery backed RAM included.
+    pub fn power_cycle(&mut self) {
+        let cartridge = match self.rom.clone().map(cartridge::from_rom) {
+            Some(Ok(cartridge)) => cartridge,
+            // The ROM was already loaded successfully once
+            Some(Err(_)) | None => cartridge::empty(),
+        };
+        self.insert(cartridge);
+    }
+
+    /// Runs the machine for one frame worth of T-cycles.
+    pub fn run_frame(&mut self) -> FrameOutput {
+        let mut cycles: ClockTicks = 0;
+        let mut frame = None;
+        while cycles + self.overshoot < CYCLES_PER_FRAME {
+            cycles += self.cpu.step(&mut self.mmu);
+            // The PPU may finish a frame anywhere in the window, keep the last one
+            if let Some(pixels) = self.take_frame() {
+                frame = Some(pixels);
+            }
+        }
+        self.overshoot = cycles + self.overshoot - CYCLES_PER_FRAME;
+
+        FrameOutput {
+            frame,
+            cycles,
+            fault: self.cpu.take_fault(),
+        }
+    }
+
+    /// Runs the next instruction or services an interrupt, and returns the T-cycles it took.
+    pub fn step_instruction(&mut self) -> ClockTicks {
+        self.cpu.step(&mut self.mmu)
+    }
+
+    /// Returns the last frame drawn by the PPU once it is complete.
+    pub fn take_frame(&mut self) -> Option<Box<PixelBuffer>> {
+        self.mmu
+            .ppu_mut()
+            .take_frame()
+            .map(|pixels| Box::new(*pixels))
+    }
+
+    /// What the PPU has drawn so far, the current frame above the line being drawn and the
+    /// previous one below.
+    pub fn screen(&self) -> &PixelBuffer {
+        self.mmu.ppu().pixel_buffer()
+    }
+
+    /// Returns the last CPU fault once, so it can be reported.
+    pub fn take_fault(&mut self) -> Option<Fault> {
+        self.cpu.take_fault()
+    }
+
+    /// Holds down exactly the given buttons, every other one is released.
+    pub fn set_buttons(&mut self, pressed: &[DmgButton]) {
+        for button in DmgButton::ALL {
+            match pressed.contains(&button) {
+                true => self.mmu.press_button(button),
+                false => self.mmu.release_button(button),
+            }
+        }
+    }
+
+    pub fn press_button(&mut self, button: DmgButton) {
+        self.mmu.press_button(button);
+    }
+
+    pub fn release_button(&mut self, button: DmgButton) {
+        self.mmu.release_button(button);
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// CRC-32 of the loaded ROM.
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// Whether the machine starts by running a boot ROM.
+    pub fn has_boot_rom(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.cpu.registers
+    }
+
+    pub fn interrupt_state(&mut self) -> InterruptState {
+        let ime = self.cpu.ime();
+        self.mmu.interrupts().state(ime)
+    }
+
+    /// Reads a byte without side effects or timing.
+    pub fn peek_8(&self, address: u16) -> u8 {
+        self.mmu.peek_8(address)
+    }
+
+    /// The whole address space as seen by the debugger.
+    pub fn memory_dump(&self) -> Arc<[u8; 0x10000]> {
+        self.mmu.get_memory_dump()
+    }
+
+    /// Bytes sent over the serial port since the last call.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.mmu.take_serial_output()
+    }
+
+    /// RAM of a battery backed cartridge, to be saved between sessions.
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.mmu.battery_ram()
+    }
+
+    pub fn load_battery_ram(&mut self, ram: &[u8]) {
+        self.mmu.load_battery_ram(ram);
+    }
+
+    /// Snapshot of the whole machine, see [`save_state`] for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        let header = Header::new(self.rom_hash, self.model, self.screen());
+        let mut state = save_state::writer(&header);
+        self.save_sections(&mut state);
+        state.into_bytes()
+    }
+
+    /// Save state without its header, which only loads back into this machine with the ROM it
+    /// was taken with. Meant for states kept in memory, it leaves out the thumbnail.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut state = Writer::new();
+        self.save_sections(&mut state);
+        state.into_bytes()
+    }
+
+    fn save_sections(&self, state: &mut Writer) {
+        state.section(Section::Cpu, |state| {
+            self.cpu.save(state);
+            state.u64(self.overshoot as u64);
+        });
+        self.mmu.save_state(state);
+    }
+
+    /// Restores a snapshot taken with the same ROM and model. On error, the machine is left
+    /// untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> save_state::Result<()> {
+        let state = SaveState::parse(data)?;
+        if state.header.rom_hash != self.rom_hash {
+            return Err(save_state::Error::RomMismatch {
+                expected: self.rom_hash,
+                found: state.header.rom_hash,
+            });
+        }
+        if state.header.model != self.model {
+            return Err(save_state::Error::ModelMismatch {
+                expected: self.model,
+                found: state.header.model,
+            });
+        }
+
+        self.restore_or_undo(&state.sections)
+    }
+
+    /// Restores a snapshot taken on this machine. On error, the machine is left untouched.
+    pub fn load_snapshot(&mut self, data: &[u8]) -> save_state::Result<()> {
+        self.restore_or_undo(&Sections::parse(data)?)
+    }
+
+    fn restore_or_undo(&mut self, state: &Sections) -> save_state::Result<()> {
+        let backup = self.snapshot();
+        if let Err(err) = self.restore(state) {
+            // A snapshot that was just taken always loads, this undoes the partial restore
+            self.restore(&Sections::parse(&backup)?)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self, state: &Sections) -> save_state::Result<()> {
+        let mut cpu = state.section(Section::Cpu)?;
+        self.cpu.load(&mut cpu)?;
+        self.overshoot = cpu.u64()? as ClockTicks;
+        self.mmu.load_state(state)
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.cpu.tracer.as_ref()
+    }
+
+    /// Logs CPU accesses to VRAM and OAM while the PPU has them locked.
+    pub fn set_log_illegal_access(&mut self, enabled: bool) {
+        self.log_illegal_access = enabled;
+        self.mmu.set_log_illegal_access(enabled);
+    }
+}
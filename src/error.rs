@@ -0,0 +1,49 @@
+//! Errors and faults reported by the emulator core.
+//!
+//! Bad input that the hardware copes with, such as a game writing an unexpected value to a
+//! register, is emulated the way the hardware reacts to it. An [`Error`] is returned when the
+//! core cannot do what it was asked, like loading an invalid ROM. A [`Fault`] is a condition the
+//! emulated machine ends up in and cannot recover from by itself: the machine keeps running, but
+//! frontends are expected to stop and report it.
+
+use std::{fmt, result};
+
+use thiserror::Error;
+
+use crate::{boot_rom, cartridge, hash_log, movie, save_state};
+
+/// Any error of the emulator core.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    BootRom(#[from] boot_rom::Error),
+    #[error(transparent)]
+    Cartridge(#[from] cartridge::Error),
+    #[error(transparent)]
+    SaveState(#[from] save_state::Error),
+    #[error(transparent)]
+    Movie(#[from] movie::Error),
+    #[error(transparent)]
+    HashLog(#[from] hash_log::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Condition the emulated machine can't recover from without a reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The CPU fetched an opcode that does not exist and locked up.
+    IllegalOpcode { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::IllegalOpcode { pc, opcode } => write!(
+                f,
+                "CPU locked up on illegal opcode {:#04X} at {:#06X}",
+                opcode, pc
+            ),
+        }
+    }
+}
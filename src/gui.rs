@@ -9,16 +9,35 @@ use eframe::{
 };
 use tracing::error;
 
-use crate::{
+use dmg_rs::{
+    clock::Rate,
     disassembler,
-    graphics::{draw_bg_map, draw_tile_data},
+    error::Fault,
+    graphics::{Color, ColorPalette, DmgPalette},
+    interrupt::InterruptState,
+    joypad::DmgButton,
     lr35902::{Register16, Register8, Registers},
     ppu::PixelBuffer,
-    thread::{DmgButton, DmgMessage, GuiMessage},
 };
 
+use crate::{
+    dmg::SAVE_STATE_SLOTS,
+    thread::{DmgMessage, GuiMessage, MovieStart, MovieStatus},
+};
+
+/// Keys loading the save state slots, saving with Shift held.
+const SAVE_STATE_KEYS: [Key; SAVE_STATE_SLOTS] = [Key::F1, Key::F2, Key::F3, Key::F4];
+
+/// Speed multipliers selectable with the - and + keys.
+const SPEED_MULTIPLIERS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+
 struct State {
     registers: Registers,
+    interrupts: InterruptState,
+    fault: Option<Fault>,
+    speed: Option<f64>,
+    movie: Option<MovieStatus>,
     memory: Arc<[u8; 0x10000]>,
 }
 
@@ -26,9 +45,15 @@ pub struct Gui {
     tile_texture_handle: TextureHandle,
     bg_map_texture_handle: TextureHandle,
     screen_texture_handle: TextureHandle,
+    /// Screens saved in the save state slots, by slot number minus 1.
+    save_state_thumbnails: Vec<Option<TextureHandle>>,
     tx: Sender<GuiMessage>,
     rx: Receiver<DmgMessage>,
     state: State,
+    /// Index in `SPEED_MULTIPLIERS` of the speed to run at outside of turbo.
+    speed_multiplier: usize,
+    turbo: bool,
+    rewinding: bool,
     rom_label_content: String,
     ram_label_content: String,
     memory_label_content: String,
@@ -58,12 +83,20 @@ impl Gui {
             tile_texture_handle,
             bg_map_texture_handle,
             screen_texture_handle,
+            save_state_thumbnails: vec![None; SAVE_STATE_SLOTS],
             tx,
             rx,
             state: State {
                 registers: Default::default(),
+                interrupts: Default::default(),
+                fault: None,
+                speed: None,
+                movie: None,
                 memory: Arc::new([0u8; 0x10000]),
             },
+            speed_multiplier: NORMAL_SPEED,
+            turbo: false,
+            rewinding: false,
             rom_label_content: "".to_string(),
             ram_label_content: "".to_string(),
             memory_label_content: "".to_string(),
@@ -86,20 +119,39 @@ impl Gui {
     }
 
     fn update_screen_texture(&mut self, _ctx: &egui::Context, pixel_buffer: Arc<PixelBuffer>) {
-        let mut image = ColorImage::new([160, 144], Color32::WHITE);
-        for (i, pixel) in pixel_buffer.iter().enumerate() {
-            image[(i % 160, i / 160)] = pixel.clone();
-        }
-
+        let image = draw_screen(&pixel_buffer);
         self.screen_texture_handle.set(image, Default::default());
     }
 
+    fn update_thumbnail(&mut self, ctx: &egui::Context, slot: usize, pixels: Arc<PixelBuffer>) {
+        let image = draw_screen(&pixels);
+        match &mut self.save_state_thumbnails[slot - 1] {
+            Some(texture) => texture.set(image, Default::default()),
+            thumbnail => {
+                let name = format!("SaveState{}", slot);
+                *thumbnail = Some(ctx.load_texture(name, image, Default::default()));
+            }
+        }
+    }
+
     fn handle_dmg_message(&mut self, ctx: &egui::Context) {
         while let Ok(message) = self.rx.try_recv() {
             match message {
                 DmgMessage::RegistersStatus(registers) => self.state.registers = registers,
+                DmgMessage::InterruptState(interrupts) => self.state.interrupts = interrupts,
                 DmgMessage::MemoryState(state) => self.update_memory_state(ctx, state),
                 DmgMessage::Render(pixel_buffer) => self.update_screen_texture(ctx, pixel_buffer),
+                DmgMessage::Fault(fault) => self.state.fault = Some(fault),
+                DmgMessage::Speed(speed) => self.state.speed = Some(speed),
+                DmgMessage::Movie(movie) => self.state.movie = movie,
+                DmgMessage::SaveStateThumbnail(slot, pixels) => {
+                    self.update_thumbnail(ctx, slot, pixels)
+                }
+                DmgMessage::Reset => self.state.fault = None,
+                DmgMessage::RomLoaded => {
+                    self.state.fault = None;
+                    self.save_state_thumbnails.fill(None);
+                }
             }
         }
     }
@@ -144,12 +196,32 @@ impl Gui {
         });
     }
 
+    fn ui_interrupts(&self, ui: &mut egui::Ui) {
+        let interrupts = &self.state.interrupts;
+        ui.vertical(|ui| {
+            ui.heading("Interrupts");
+            ui.horizontal(|ui| {
+                ui.monospace(format!("IME {}", interrupts.ime as u8));
+                ui.monospace(format!("IE {:#04X}", interrupts.enabled));
+                ui.monospace(format!("IF {:#04X}", interrupts.requested));
+            });
+            let pending: Vec<String> = interrupts
+                .pending()
+                .map(|interrupt| format!("{:?}", interrupt))
+                .collect();
+            ui.monospace(format!("Pending {}", pending.join(" ")));
+            if let Some(fault) = self.state.fault {
+                ui.colored_label(Color32::RED, format!("{}, paused", fault));
+            }
+        });
+    }
+
     fn format_ram_label(&self, section: &[u8], offset: u16, length: usize) -> String {
-        let mut res = format!("      00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F");
+        let mut res = "      00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F".to_string();
         for i in 0x0..length / 0x10 {
             let mut line = format!("\n{:0>4X} ", i * 0x10 + offset as usize);
             for j in 0x0..0x10 {
-                line.push_str(format!(" {:02X}", section[(i * 0x10 + j) as usize]).as_str());
+                line.push_str(format!(" {:02X}", section[i * 0x10 + j]).as_str());
             }
             res.push_str(line.as_str());
         }
@@ -243,53 +315,208 @@ impl Gui {
     }
 
     fn ui_screen(&self, ui: &mut egui::Ui) {
-        ui.add(
-            egui::Image::new(egui::load::SizedTexture::from_handle(
-                &self.screen_texture_handle,
-            ))
-            .fit_to_original_size(2f32),
-        );
+        ui.vertical(|ui| {
+            ui.add(
+                egui::Image::new(egui::load::SizedTexture::from_handle(
+                    &self.screen_texture_handle,
+                ))
+                .fit_to_original_size(2f32),
+            );
+            if let Some(speed) = self.state.speed {
+                let setting = match (self.rewinding, self.turbo) {
+                    (true, _) => "rewind".to_owned(),
+                    (false, true) => "turbo".to_owned(),
+                    (false, false) => format!("{}x", SPEED_MULTIPLIERS[self.speed_multiplier]),
+                };
+                ui.monospace(format!("Speed: {:.0}% ({})", speed * 100.0, setting));
+            }
+            if let Some(movie) = &self.state.movie {
+                let mode = match movie.read_only {
+                    true => "read-only",
+                    false => "recording",
+                };
+                ui.monospace(format!(
+                    "Movie: {}/{} ({})",
+                    movie.frame, movie.length, mode
+                ));
+            }
+        });
+    }
+
+    fn ui_save_states(&self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for (slot, thumbnail) in (1..).zip(&self.save_state_thumbnails) {
+                ui.vertical(|ui| {
+                    ui.label(format!("Slot {} (F{})", slot, slot));
+                    match thumbnail {
+                        Some(texture) => {
+                            ui.add(
+                                egui::Image::new(egui::load::SizedTexture::from_handle(texture))
+                                    .fit_to_original_size(0.5f32),
+                            );
+                        }
+                        None => {
+                            ui.label("Empty");
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    fn handle_movie_inputs(&mut self, ctx: &egui::Context) {
+        let (record, shift) = ctx.input(|i| (i.key_pressed(Key::V), i.modifiers.shift));
+        let message = if record {
+            let start = match shift {
+                true => MovieStart::PowerOn,
+                false => MovieStart::CurrentState,
+            };
+            Some(GuiMessage::RecordMovie(start))
+        } else if ctx.input(|i| i.key_pressed(Key::Y)) {
+            Some(GuiMessage::ToggleMovieReadOnly)
+        } else if ctx.input(|i| i.key_pressed(Key::End)) {
+            Some(GuiMessage::StopMovie)
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            if let Err(err) = self.tx.send(message) {
+                error!("Could not send Movie message: {:?}", err);
+            }
+        }
+    }
+
+    fn handle_reset_inputs(&mut self, ctx: &egui::Context) {
+        let (pressed, modifiers) = ctx.input(|i| (i.key_pressed(Key::F5), i.modifiers));
+        if pressed {
+            let message = match (modifiers.shift, modifiers.command) {
+                (false, _) => GuiMessage::Reset,
+                (true, randomize_ram) => GuiMessage::PowerCycle { randomize_ram },
+            };
+            if let Err(err) = self.tx.send(message) {
+                error!("Could not send Reset message: {:?}", err);
+            }
+        }
+
+        // Dropping a ROM on the window inserts it in place of the current one
+        let dropped = ctx.input(|i| i.raw.dropped_files.iter().find_map(|f| f.path.clone()));
+        if let Some(path) = dropped {
+            let message = GuiMessage::LoadRom(path.to_string_lossy().into_owned());
+            if let Err(err) = self.tx.send(message) {
+                error!("Could not send Load ROM message: {:?}", err);
+            }
+        }
+    }
+
+    fn handle_save_state_inputs(&mut self, ctx: &egui::Context) {
+        for (slot, key) in (1..).zip(SAVE_STATE_KEYS) {
+            let (pressed, shift) = ctx.input(|i| (i.key_pressed(key), i.modifiers.shift));
+            if !pressed {
+                continue;
+            }
+            let message = match shift {
+                true => GuiMessage::SaveState(slot),
+                false => GuiMessage::LoadState(slot),
+            };
+            if let Err(err) = self.tx.send(message) {
+                error!("Could not send Save State message: {:?}", err);
+            }
+        }
     }
 
     fn handle_joypad_inputs(&mut self, ctx: &egui::Context, key: Key, button: DmgButton) {
         if ctx.input(|i| i.key_pressed(key)) {
-            if let Err(_) = self.tx.send(GuiMessage::ButtonPressed(button)) {
-                error!("Could not send Joypad Input");
+            if let Err(err) = self.tx.send(GuiMessage::ButtonPressed(button)) {
+                error!("Could not send Joypad Input: {:?}", err);
             }
         } else if ctx.input(|i| i.key_released(key)) {
-            if let Err(_) = self.tx.send(GuiMessage::ButtonReleased(button)) {
-                error!("Could not send Joypad Input");
+            if let Err(err) = self.tx.send(GuiMessage::ButtonReleased(button)) {
+                error!("Could not send Joypad Input: {:?}", err);
             }
         }
     }
 
+    fn send_rate(&mut self) {
+        let rate = match self.turbo {
+            true => Rate::Uncapped,
+            false => Rate::Scaled(SPEED_MULTIPLIERS[self.speed_multiplier]),
+        };
+        if let Err(err) = self.tx.send(GuiMessage::Rate(rate)) {
+            error!("Could not send Rate message: {:?}", err);
+        }
+    }
+
+    fn handle_speed_inputs(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| i.key_pressed(Key::F)) {
+            if self.tx.send(GuiMessage::NextFrame).is_err() {
+                error!("Could not send Next Frame message");
+            }
+            if self.tx.send(GuiMessage::RequestState).is_err() {
+                error!("Could not send State Request message");
+            }
+        }
+        if ctx.input(|i| i.key_pressed(Key::Tab)) {
+            self.turbo = !self.turbo;
+            self.send_rate();
+        }
+        let rewind = match ctx.input(|i| (i.key_pressed(Key::R), i.key_released(Key::R))) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        };
+        if let Some(rewinding) = rewind {
+            self.rewinding = rewinding;
+            if let Err(err) = self.tx.send(GuiMessage::Rewind(rewinding)) {
+                error!("Could not send Rewind message: {:?}", err);
+            }
+        }
+        if ctx.input(|i| i.key_pressed(Key::Minus)) && self.speed_multiplier > 0 {
+            self.speed_multiplier -= 1;
+            self.send_rate();
+        }
+        if ctx.input(|i| i.key_pressed(Key::PlusEquals))
+            && self.speed_multiplier < SPEED_MULTIPLIERS.len() - 1
+        {
+            self.speed_multiplier += 1;
+            self.send_rate();
+        }
+        if ctx.input(|i| i.key_pressed(Key::Num1)) {
+            self.speed_multiplier = NORMAL_SPEED;
+            self.send_rate();
+        }
+    }
+
     fn handle_inputs(&mut self, ctx: &egui::Context) {
         if ctx.input(|i| i.key_pressed(Key::N)) {
-            if let Err(_) = self.tx.send(GuiMessage::NextInstruction(1)) {
+            if self.tx.send(GuiMessage::NextInstruction(1)).is_err() {
                 error!("Could not send Next Instruction message");
             }
-            if let Err(_) = self.tx.send(GuiMessage::RequestState) {
+            if self.tx.send(GuiMessage::RequestState).is_err() {
                 error!("Could not send State Request message");
             }
         }
         if ctx.input(|i| i.key_pressed(Key::M)) {
-            if let Err(_) = self.tx.send(GuiMessage::NextInstruction(20)) {
+            if self.tx.send(GuiMessage::NextInstruction(20)).is_err() {
                 error!("Could not send Next Instruction message");
             }
-            if let Err(_) = self.tx.send(GuiMessage::RequestState) {
+            if self.tx.send(GuiMessage::RequestState).is_err() {
                 error!("Could not send State Request message");
             }
         }
         if ctx.input(|i| i.key_pressed(Key::S)) {
-            if let Err(_) = self.tx.send(GuiMessage::StepMode(true)) {
-                error!("Could not send Stop message");
+            if let Err(err) = self.tx.send(GuiMessage::StepMode(true)) {
+                error!("Could not send Stop message: {:?}", err);
             }
         }
         if ctx.input(|i| i.key_pressed(Key::C)) {
-            if let Err(_) = self.tx.send(GuiMessage::StepMode(false)) {
-                error!("Could not send Continue message");
+            if let Err(err) = self.tx.send(GuiMessage::StepMode(false)) {
+                error!("Could not send Continue message: {:?}", err);
             }
         }
+        self.handle_speed_inputs(ctx);
+        self.handle_reset_inputs(ctx);
+        self.handle_save_state_inputs(ctx);
+        self.handle_movie_inputs(ctx);
         self.handle_joypad_inputs(ctx, Key::Z, DmgButton::A);
         self.handle_joypad_inputs(ctx, Key::X, DmgButton::B);
         self.handle_joypad_inputs(ctx, Key::Enter, DmgButton::Start);
@@ -309,12 +536,16 @@ impl eframe::App for Gui {
             ui.horizontal(|ui| {
                 ui.vertical(|ui| {
                     self.ui_registers(ui);
+                    self.ui_interrupts(ui);
                     self.ui_disassemble(ui);
                 });
                 self.ui_screen(ui);
                 self.ui_ram(ui);
                 self.ui_vram(ui);
             });
+            egui::Window::new("Save states")
+                .default_open(false)
+                .show(ctx, |ui| self.ui_save_states(ui));
             egui::Window::new("Memory")
                 .default_open(false)
                 .show(ctx, |ui| {
@@ -328,8 +559,64 @@ impl eframe::App for Gui {
         });
         ctx.request_repaint();
 
-        if let Err(_) = self.tx.send(GuiMessage::RequestState) {
+        if self.tx.send(GuiMessage::VSync).is_err() {
+            error!("Could not send vsync to DMG.")
+        }
+        if self.tx.send(GuiMessage::RequestState).is_err() {
             error!("Could not send state request to DMG.")
         }
     }
 }
+
+fn color32(color: Color) -> Color32 {
+    Color32::from_rgb(color.r, color.g, color.b)
+}
+
+// #[tracing::instrument]
+fn draw_screen(pixels: &PixelBuffer) -> ColorImage {
+    let mut image = ColorImage::new([160, 144], Color32::WHITE);
+    for (i, pixel) in pixels.iter().enumerate() {
+        image[(i % 160, i / 160)] = color32(*pixel);
+    }
+    image
+}
+
+fn draw_tile_data(data: &[u8], dmg_palette: DmgPalette) -> ColorImage {
+    let palette = ColorPalette::from_dmg_palette(dmg_palette);
+    let mut image = ColorImage::new([16 * 8, 24 * 8], Color32::WHITE);
+    for i in 0..(16 * 24) {
+        let data_idx = i * 16;
+        let tile_array = &data[data_idx..data_idx + 16];
+        for j in 0..8 {
+            let byte_a = tile_array[j * 2];
+            let byte_b = tile_array[j * 2 + 1];
+            for bit in 0..8 {
+                let bit_a = (byte_a.wrapping_shr(7 - bit)) & 0x01;
+                let bit_b = (byte_b.wrapping_shr(7 - bit)) & 0x01;
+                let color = (bit_b << 1) | bit_a;
+
+                let px_y = (i / 16) * 8 + j;
+                let px_x = (i % 16) * 8 + bit as usize;
+                image[(px_x, px_y)] = color32(palette[color as usize]);
+            }
+        }
+    }
+    image
+}
+
+// #[tracing::instrument]
+fn draw_bg_map(data: &[u8], tile_image: &ColorImage) -> ColorImage {
+    let mut image = ColorImage::new([32 * 8, 32 * 8], Color32::WHITE);
+    for (i, tile_idx) in data.iter().enumerate() {
+        for y in 0..8 {
+            for x in 0..8 {
+                let ipx_y = (i / 32) * 8 + y;
+                let ipx_x = (i % 32) * 8 + x;
+                let tpx_y = (*tile_idx as usize / 16) * 8 + y;
+                let tpx_x = (*tile_idx as usize % 16) * 8 + x;
+                image[(ipx_x, ipx_y)] = tile_image[(tpx_x, tpx_y)];
+            }
+        }
+    }
+    image
+}
@@ -0,0 +1,207 @@
+//! Per frame hashes of the machine, to check that a run is reproduced exactly.
+//!
+//! Frontends write the log of a movie while recording it, and playing the movie back later must
+//! give the same hashes.
+//!
+//! The log is a text file with a line per frame: the frame number, then the CRC-32 of the screen
+//! and of every save state section, in hexadecimal:
+//!
+//! ```text
+//! dmg-rs hashes 1
+//! # frame screen CPU MMU PPU timer cartridge
+//! 1 5D3A9C01 0E1B2A33 A0B1C2D3 11223344 55667788 99AABBCC
+//! ```
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    result,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::{
+    save_state::{self, SaveState, Section},
+    Emulator,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Could not read hash log: {0}.")]
+    Loading(#[from] io::Error),
+    #[error("Hash log line {line}: {reason}.")]
+    Invalid { line: usize, reason: String },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+const MAGIC: &str = "dmg-rs hashes 1";
+
+/// The log of a movie sits next to it, `game.movie` having it in `game.hashes`.
+pub fn log_path(movie_path: &str) -> PathBuf {
+    Path::new(movie_path).with_extension("hashes")
+}
+
+/// Hashes of the machine at the end of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHashes {
+    pub screen: u32,
+    /// Hashes of the save state sections, in the order of `Section::ALL`.
+    pub sections: [u32; Section::ALL.len()],
+}
+
+impl FrameHashes {
+    pub fn of(emulator: &Emulator) -> save_state::Result<Self> {
+        let screen: Vec<u8> = emulator
+            .screen()
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b])
+            .collect();
+        let state = emulator.save_state();
+        let state = SaveState::parse(&state)?;
+        let mut sections = [0; Section::ALL.len()];
+        for (hash, section) in sections.iter_mut().zip(Section::ALL) {
+            *hash = save_state::crc32(state.sections.content(section)?);
+        }
+        Ok(Self {
+            screen: save_state::crc32(&screen),
+            sections,
+        })
+    }
+}
+
+/// Where a run stopped matching the log.
+#[derive(Debug)]
+pub struct Divergence {
+    /// Frame number, counted from 1.
+    pub frame: usize,
+    pub screen: bool,
+    /// Components whose state differs.
+    pub sections: Vec<Section>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = self.sections.iter().map(Section::to_string).collect();
+        if self.screen {
+            parts.insert(0, "screen".to_owned());
+        }
+        write!(f, "frame {}: {} differ", self.frame, parts.join(", "))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HashLog {
+    /// Hashes of every frame, the first frame being at index 0.
+    pub frames: Vec<FrameHashes>,
+}
+
+impl HashLog {
+    pub fn from_file(path: &str) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Sets the hashes of frame `frame`, counted from 1, replacing the logged ones. The frames
+    /// before it must already be logged.
+    pub fn set(&mut self, frame: usize, hashes: FrameHashes) {
+        match self.frames.get_mut(frame - 1) {
+            Some(logged) => *logged = hashes,
+            None => self.frames.push(hashes),
+        }
+    }
+
+    /// Compares the hashes of frame `frame`, counted from 1, with the log. Frames past the end
+    /// of the log are not checked.
+    pub fn check(&self, frame: usize, hashes: &FrameHashes) -> Option<Divergence> {
+        let expected = self.frames.get(frame.checked_sub(1)?)?;
+        if expected == hashes {
+            return None;
+        }
+
+        let sections = Section::ALL
+            .into_iter()
+            .zip(expected.sections.iter().zip(&hashes.sections))
+            .filter(|(_, (expected, actual))| expected != actual)
+            .map(|(section, _)| section)
+            .collect();
+        Some(Divergence {
+            frame,
+            screen: expected.screen != hashes.screen,
+            sections,
+        })
+    }
+}
+
+impl fmt::Display for HashLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", MAGIC)?;
+        let names: Vec<String> = Section::ALL.iter().map(Section::to_string).collect();
+        writeln!(f, "# frame screen {}", names.join(" "))?;
+        for (index, hashes) in self.frames.iter().enumerate() {
+            write!(f, "{} {:08X}", index + 1, hashes.screen)?;
+            for hash in hashes.sections {
+                write!(f, " {:08X}", hash)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for HashLog {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut lines = s
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let invalid = |line: usize, reason: String| Error::Invalid { line, reason };
+
+        match lines.next() {
+            Some((_, MAGIC)) => (),
+            Some((line, _)) => return Err(invalid(line, format!("expected {}", MAGIC))),
+            None => return Err(invalid(1, format!("expected {}", MAGIC))),
+        }
+
+        let mut frames = Vec::new();
+        for (line, content) in lines {
+            let fields: Vec<_> = content.split_whitespace().collect();
+            let [frame, screen, sections @ ..] = &fields[..] else {
+                return Err(invalid(line, "expected FRAME SCREEN SECTIONS".to_owned()));
+            };
+            if *frame != (frames.len() + 1).to_string() {
+                return Err(invalid(
+                    line,
+                    format!("expected frame {}", frames.len() + 1),
+                ));
+            }
+            if sections.len() != Section::ALL.len() {
+                return Err(invalid(
+                    line,
+                    format!("expected {} section hashes", Section::ALL.len()),
+                ));
+            }
+
+            let parse = |hash: &str| {
+                u32::from_str_radix(hash, 16)
+                    .map_err(|err| invalid(line, format!("invalid hash {}: {}", hash, err)))
+            };
+            let mut hashes = FrameHashes {
+                screen: parse(screen)?,
+                sections: [0; Section::ALL.len()],
+            };
+            for (hash, text) in hashes.sections.iter_mut().zip(sections) {
+                *hash = parse(text)?;
+            }
+            frames.push(hashes);
+        }
+        Ok(Self { frames })
+    }
+}
@@ -0,0 +1,119 @@
+use crate::save_state::{self, Reader, Snapshot, Writer};
+
+/// Interrupt sources, in priority order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// Every interrupt, from the highest to the lowest priority.
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Bit of the interrupt in IF and IE.
+    pub fn bit(self) -> u8 {
+        1u8 << self as u8
+    }
+
+    /// Address the CPU jumps to when servicing the interrupt.
+    pub fn vector(self) -> u16 {
+        0x0040 + 8 * self as u16
+    }
+}
+
+/// IF and IE bits as seen by the debugger.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InterruptState {
+    pub requested: u8,
+    pub enabled: u8,
+    pub ime: bool,
+}
+
+impl InterruptState {
+    /// Interrupts that are both requested and enabled, regardless of IME.
+    pub fn pending(&self) -> impl Iterator<Item = Interrupt> + '_ {
+        Interrupt::ALL
+            .into_iter()
+            .filter(|interrupt| self.requested & self.enabled & interrupt.bit() != 0)
+    }
+}
+
+/// Holds IF and IE. Peripherals request interrupts here and the CPU services them from here.
+#[derive(Debug, Default)]
+pub struct InterruptController {
+    requested: u8,
+    enabled: u8,
+}
+
+const INTERRUPT_MASK: u8 = 0x1F;
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.requested |= interrupt.bit();
+    }
+
+    /// Clears the request once the CPU starts servicing the interrupt.
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.requested &= !interrupt.bit();
+    }
+
+    /// Highest priority interrupt that is both requested and enabled.
+    pub fn highest_pending(&self) -> Option<Interrupt> {
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| self.requested & self.enabled & interrupt.bit() != 0)
+    }
+
+    /// The upper 3 bits of IF are unused and read as 1.
+    pub fn read_if(&self) -> u8 {
+        self.requested | !INTERRUPT_MASK
+    }
+
+    pub fn write_if(&mut self, value: u8) {
+        self.requested = value & INTERRUPT_MASK;
+    }
+
+    /// All 8 bits of IE are writable, even if only the lower 5 enable interrupts.
+    pub fn read_ie(&self) -> u8 {
+        self.enabled
+    }
+
+    pub fn write_ie(&mut self, value: u8) {
+        self.enabled = value;
+    }
+
+    pub fn state(&self, ime: bool) -> InterruptState {
+        InterruptState {
+            requested: self.requested,
+            enabled: self.enabled,
+            ime,
+        }
+    }
+}
+
+impl Snapshot for InterruptController {
+    fn save(&self, state: &mut Writer) {
+        state.u8(self.requested);
+        state.u8(self.enabled);
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        self.requested = state.u8()? & INTERRUPT_MASK;
+        self.enabled = state.u8()?;
+        Ok(())
+    }
+}
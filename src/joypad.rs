@@ -1,9 +1,62 @@
-use crate::thread::DmgButton;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::save_state::{self, Reader, Snapshot, Writer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmgButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+    Select,
+}
+
+impl DmgButton {
+    pub const ALL: [DmgButton; 8] = [
+        DmgButton::Up,
+        DmgButton::Down,
+        DmgButton::Left,
+        DmgButton::Right,
+        DmgButton::A,
+        DmgButton::B,
+        DmgButton::Start,
+        DmgButton::Select,
+    ];
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown button {0}, expected one of up, down, left, right, a, b, start, select.")]
+pub struct UnknownButton(String);
+
+impl FromStr for DmgButton {
+    type Err = UnknownButton;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "up" => Ok(DmgButton::Up),
+            "down" => Ok(DmgButton::Down),
+            "left" => Ok(DmgButton::Left),
+            "right" => Ok(DmgButton::Right),
+            "a" => Ok(DmgButton::A),
+            "b" => Ok(DmgButton::B),
+            "start" => Ok(DmgButton::Start),
+            "select" => Ok(DmgButton::Select),
+            _ => Err(UnknownButton(s.to_owned())),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum SelectMode {
     Buttons,
     DirectionalPad,
+    /// Both groups drive the input lines, a line is low when a button of either group is held.
+    Both,
     Other,
 }
 
@@ -14,6 +67,12 @@ pub struct Joypad {
     select_mode: SelectMode,
 }
 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Joypad {
     pub fn new() -> Self {
         Self {
@@ -23,7 +82,20 @@ impl Joypad {
         }
     }
 
-    pub fn button_pressed(&mut self, button: DmgButton) {
+    /// State of the P1 input lines for the current selection, a pressed button reads as 0.
+    fn lines(&self) -> u8 {
+        match self.select_mode {
+            SelectMode::Buttons => self.buttons,
+            SelectMode::DirectionalPad => self.d_pad,
+            SelectMode::Both => self.buttons & self.d_pad,
+            SelectMode::Other => 0x0F,
+        }
+    }
+
+    /// Returns whether a selected input line went from high to low, which requests the joypad
+    /// interrupt.
+    pub fn button_pressed(&mut self, button: DmgButton) -> bool {
+        let lines = self.lines();
         let clear = |target: &mut u8, n: u8| {
             *target &= !(1u8 << n);
         };
@@ -38,6 +110,7 @@ impl Joypad {
             DmgButton::Select => clear(&mut self.buttons, 2),
         }
         tracing::info!(?button, ?self.d_pad, ?self.buttons, "Button pressed");
+        lines & !self.lines() != 0
     }
 
     pub fn button_released(&mut self, button: DmgButton) {
@@ -56,24 +129,55 @@ impl Joypad {
         }
     }
 
-    pub fn write(&mut self, value: u8) {
+    /// Returns whether selecting another group pulled an input line low, because one of its
+    /// buttons is held.
+    pub fn write(&mut self, value: u8) -> bool {
+        let lines = self.lines();
         let value = (value >> 4) & 0x03;
         match value {
             0x01 => self.select_mode = SelectMode::DirectionalPad,
             0x02 => self.select_mode = SelectMode::Buttons,
             0x03 => self.select_mode = SelectMode::Other,
-            _ => unreachable!(),
+            _ => self.select_mode = SelectMode::Both,
         };
-        tracing::info!(?self.select_mode, "Mode selected")
+        tracing::info!(?self.select_mode, "Mode selected");
+        lines & !self.lines() != 0
     }
 
     pub fn read(&self) -> u8 {
         let res = match self.select_mode {
             SelectMode::Buttons => 0x10 | self.buttons,
             SelectMode::DirectionalPad => 0x20 | self.d_pad,
+            SelectMode::Both => self.buttons & self.d_pad,
             SelectMode::Other => 0x3F,
         };
         tracing::info!(res = format!("{:X}", res), ?self.select_mode, "Joypad Read");
         res
     }
 }
+
+impl Snapshot for Joypad {
+    fn save(&self, state: &mut Writer) {
+        state.u8(self.buttons);
+        state.u8(self.d_pad);
+        state.u8(match self.select_mode {
+            SelectMode::Buttons => 0,
+            SelectMode::DirectionalPad => 1,
+            SelectMode::Other => 2,
+            SelectMode::Both => 3,
+        });
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        self.buttons = state.u8()? & 0x0F;
+        self.d_pad = state.u8()? & 0x0F;
+        self.select_mode = match state.u8()? {
+            0 => SelectMode::Buttons,
+            1 => SelectMode::DirectionalPad,
+            2 => SelectMode::Other,
+            3 => SelectMode::Both,
+            _ => return Err(save_state::Error::Invalid("unknown joypad selection")),
+        };
+        Ok(())
+    }
+}
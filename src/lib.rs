@@ -0,0 +1,31 @@
+//! Game Boy (DMG) emulator core.
+//!
+//! [`Emulator`] runs the machine one frame or one instruction at a time and does not depend on
+//! any frontend. The desktop GUI in this package is one frontend built on it.
+
+pub mod boot_rom;
+pub mod bus;
+pub mod cartridge;
+pub mod clock;
+pub mod disassembler;
+mod dma;
+pub mod emulator;
+pub mod error;
+pub mod graphics;
+pub mod hash_log;
+pub mod interrupt;
+pub mod joypad;
+pub mod lr35902;
+mod mmu;
+pub mod model;
+pub mod movie;
+pub mod ppu;
+pub mod rewind;
+pub mod save_state;
+mod scheduler;
+mod serial;
+mod timer;
+pub mod tracer;
+
+pub use emulator::{Emulator, FrameOutput};
+pub use error::{Error, Fault};
@@ -1,6 +1,13 @@
-use std::{cell::RefCell, rc::Rc};
+use tracing::error;
 
-use crate::{dmg::ClockTicks, mmu::MemoryMapUnit, tracer::Tracer};
+use crate::{
+    bus::Bus,
+    clock::ClockTicks,
+    error::Fault,
+    model::Model,
+    save_state::{self, Reader, Snapshot, Writer},
+    tracer::Tracer,
+};
 
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
@@ -111,10 +118,6 @@ impl Registers {
             Register16::PC => self.pc,
         }
     }
-    pub fn inc_pc(&mut self, value: u16) {
-        self.pc += value;
-    }
-
     pub fn set_zero_flag(&mut self, value: bool) {
         let f = self.get_8(Register8::F);
         if value {
@@ -178,162 +181,241 @@ impl Registers {
 pub struct LR35902 {
     pub tracer: Option<Tracer>,
     pub registers: Registers,
-    mmu: Rc<RefCell<MemoryMapUnit>>,
     ime: bool,
+    /// Instructions left before IME gets set by EI.
+    ime_delay: u8,
     halted: bool,
+    /// Set when HALT is executed with IME off and an interrupt already pending. The CPU does
+    /// not halt, and the next opcode is fetched without incrementing PC.
+    halt_bug: bool,
+    /// Set by an illegal opcode, only a reset gets the CPU running again.
+    locked_up: bool,
+    /// T-cycles spent by the current step.
+    cycles: ClockTicks,
+    /// Fault not yet reported to the frontend.
+    fault: Option<Fault>,
 }
 
-pub const VBLANKBIT: u8 = 1u8 << 0u8;
-pub const LCDBIT: u8 = 1u8 << 1u8;
-pub const TIMERBIT: u8 = 1u8 << 2u8;
-pub const SERIALBIT: u8 = 1u8 << 3u8;
-pub const JOYPADBIT: u8 = 1u8 << 4u8;
+impl Default for LR35902 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl LR35902 {
-    pub fn new(mmu: Rc<RefCell<MemoryMapUnit>>) -> Self {
+    pub fn new() -> Self {
         LR35902 {
             tracer: None,
-            mmu,
             registers: Default::default(),
             ime: false,
+            ime_delay: 0,
             halted: false,
+            halt_bug: false,
+            locked_up: false,
+            cycles: 0,
+            fault: None,
         }
     }
 
-    fn check_for_interrupt(&mut self) -> Option<()> {
-        let interrupt_flag = self.mmu.borrow().read_8(0xFF0F);
-        let interrupt_enable = self.mmu.borrow().read_8(0xFFFF);
+    /// Interrupt master enable flag.
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
 
-        if interrupt_enable & VBLANKBIT != 0 && interrupt_flag & VBLANKBIT != 0 {
-            self.call_vec(0x0040);
-            let interrupt_flag = interrupt_flag & !VBLANKBIT;
-            self.mmu.borrow_mut().write_8(0xFF0F, interrupt_flag);
-            return Some(());
-        }
+    /// Sets the registers to the values the model's boot ROM leaves when jumping to 0x0100.
+    ///
+    /// Some of them depend on the cartridge header, which is read through the bus.
+    pub fn skip_boot(&mut self, model: Model, bus: &impl Bus) {
+        let header_checksum = bus.peek_8(0x014D);
+        let flags = match header_checksum {
+            0 => 0x80,
+            _ => 0xB0,
+        };
+        let (af, bc, de, hl) = match model {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x0100 | flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF00 | flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::CgbDmg => {
+                // The CGB boot ROM only looks at the title of games published by Nintendo
+                let old_licensee = bus.peek_8(0x014B);
+                let new_licensee = [bus.peek_8(0x0144), bus.peek_8(0x0145)];
+                let title_checksum = match (old_licensee, new_licensee) {
+                    (0x01, _) | (0x33, [b'0', b'1']) => (0x0134..=0x0143)
+                        .fold(0u8, |acc, address| acc.wrapping_add(bus.peek_8(address))),
+                    _ => 0x00,
+                };
+                let hl = match title_checksum {
+                    0x43 | 0x58 => 0x991A,
+                    _ => 0x007C,
+                };
+                (0x1180, (title_checksum as u16) << 8, 0x0008, hl)
+            }
+        };
+        self.registers.set_16(Register16::AF, af);
+        self.registers.set_16(Register16::BC, bc);
+        self.registers.set_16(Register16::DE, de);
+        self.registers.set_16(Register16::HL, hl);
+        self.registers.set_16(Register16::SP, 0xFFFE);
+        self.registers.set_16(Register16::PC, 0x0100);
+    }
+
+    /// Pushes PC and jumps to the vector of the highest priority pending interrupt.
+    ///
+    /// The interrupt is picked after the high byte of PC is pushed. If that push overwrites IE
+    /// and no enabled interrupt is left pending, the CPU jumps to 0x0000 instead.
+    fn dispatch_interrupt(&mut self, bus: &mut impl Bus) {
+        self.ime = false;
+        let [low, high] = self.registers.pc.to_le_bytes();
+        let sp = self.registers.get_16(Register16::SP);
+        self.idle(bus);
+        self.idle(bus);
+
+        self.write_8(bus, sp.wrapping_sub(1), high);
+        let interrupt = bus.interrupts().highest_pending();
+        self.write_8(bus, sp.wrapping_sub(2), low);
+        self.registers.set_16(Register16::SP, sp.wrapping_sub(2));
+
+        let vector = match interrupt {
+            Some(interrupt) => {
+                bus.interrupts().acknowledge(interrupt);
+                interrupt.vector()
+            }
+            None => 0x0000,
+        };
+        self.registers.set_16(Register16::PC, vector);
+        self.idle(bus);
+    }
 
-        if interrupt_enable & LCDBIT != 0 && interrupt_flag & LCDBIT != 0 {
-            self.call_vec(0x0048);
-            let interrupt_flag = interrupt_flag & !LCDBIT;
-            self.mmu.borrow_mut().write_8(0xFF0F, interrupt_flag);
-            return Some(());
-        }
+    /// Returns the last fault once, so it can be reported.
+    pub fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
+    }
 
-        if interrupt_enable & TIMERBIT != 0 && interrupt_flag & TIMERBIT != 0 {
-            self.call_vec(0x0050);
-            let interrupt_flag = interrupt_flag & !TIMERBIT;
-            self.mmu.borrow_mut().write_8(0xFF0F, interrupt_flag);
-            return Some(());
-        }
+    /// Runs the next instruction or services an interrupt, and returns the T-cycles it took.
+    ///
+    /// Each memory access and internal cycle lets the rest of the system run for one M-cycle.
+    pub fn step(&mut self, bus: &mut impl Bus) -> ClockTicks {
+        self.cycles = 0;
 
-        if interrupt_enable & SERIALBIT != 0 && interrupt_flag & SERIALBIT != 0 {
-            self.call_vec(0x0058);
-            let interrupt_flag = interrupt_flag & !SERIALBIT;
-            self.mmu.borrow_mut().write_8(0xFF0F, interrupt_flag);
-            return Some(());
+        // A locked up CPU ignores interrupts, the rest of the system keeps running
+        if self.locked_up || bus.is_stopped() {
+            self.idle(bus);
+            return self.cycles;
         }
 
-        if interrupt_enable & JOYPADBIT != 0 && interrupt_flag & JOYPADBIT != 0 {
-            self.call_vec(0x0060);
-            let interrupt_flag = interrupt_flag & !JOYPADBIT;
-            self.mmu.borrow_mut().write_8(0xFF0F, interrupt_flag);
-            return Some(());
+        // EI takes effect after the instruction following it
+        if self.ime_delay > 0 {
+            self.ime_delay -= 1;
+            self.ime = self.ime_delay == 0;
         }
 
-        None
-    }
-
-    pub fn step(&mut self) -> ClockTicks {
-        if self.ime == true {
-            if let Some(()) = self.check_for_interrupt() {
-                self.ime = false;
-                self.halted = false;
-                return 20;
+        let pending = bus.interrupts().highest_pending().is_some();
+        if self.halted {
+            // A pending interrupt wakes the CPU up even when IME is off
+            self.idle(bus);
+            if !pending {
+                return self.cycles;
             }
+            self.halted = false;
         }
 
-        if self.halted == true {
-            return 0;
+        if self.ime && pending {
+            self.dispatch_interrupt(bus);
+            return self.cycles;
         }
 
-        self.next_instruction()
+        let duration = self.next_instruction(bus);
+        // Internal cycles that are not placed explicitly come after the memory accesses
+        while self.cycles < duration {
+            self.idle(bus);
+        }
+        self.cycles
     }
 
-    pub fn next_instruction(&mut self) -> usize {
-        let opcode = self.pc_next_8();
+    pub fn next_instruction(&mut self, bus: &mut impl Bus) -> usize {
+        let opcode = match self.halt_bug {
+            true => {
+                self.halt_bug = false;
+                self.read_8(bus, self.registers.pc)
+            }
+            false => self.pc_next_8(bus),
+        };
         if let Some(ref mut tracer) = self.tracer {
-            tracer.trace(opcode, self.registers.pc, self.mmu.borrow());
+            tracer.trace(opcode, self.registers.pc, bus);
         }
         match opcode {
             // Opcodes 0x
             0x00 => 4,
-            0x01 => self.load_16_immediate(Register16::BC),
-            0x02 => self.load_8_at(Register16::BC, Register8::A),
+            0x01 => self.load_16_immediate(bus, Register16::BC),
+            0x02 => self.load_8_at(bus, Register16::BC, Register8::A),
             0x03 => self.inc_16(Register16::BC),
             0x04 => self.inc_8(Register8::B),
             0x05 => self.dec_8(Register8::B),
-            0x06 => self.load_8_immediate(Register8::B),
+            0x06 => self.load_8_immediate(bus, Register8::B),
             0x07 => self.rotate_left_accumulator(false),
-            0x08 => self.load_16_at_immediate(Register16::SP),
+            0x08 => self.load_16_at_immediate(bus, Register16::SP),
             0x09 => self.add_16(Register16::HL, Register16::BC),
-            0x0A => self.load_8_from(Register8::A, Register16::BC),
+            0x0A => self.load_8_from(bus, Register8::A, Register16::BC),
             0x0B => self.dec_16(Register16::BC),
             0x0C => self.inc_8(Register8::C),
             0x0D => self.dec_8(Register8::C),
-            0x0E => self.load_8_immediate(Register8::C),
+            0x0E => self.load_8_immediate(bus, Register8::C),
             0x0F => self.rotate_right_accumulator(false),
 
             // Opcodes 1x
-            0x10 => self.stop(),
-            0x11 => self.load_16_immediate(Register16::DE),
-            0x12 => self.load_8_at(Register16::DE, Register8::A),
+            0x10 => self.stop(bus),
+            0x11 => self.load_16_immediate(bus, Register16::DE),
+            0x12 => self.load_8_at(bus, Register16::DE, Register8::A),
             0x13 => self.inc_16(Register16::DE),
             0x14 => self.inc_8(Register8::D),
             0x15 => self.dec_8(Register8::D),
-            0x16 => self.load_8_immediate(Register8::D),
+            0x16 => self.load_8_immediate(bus, Register8::D),
             0x17 => self.rotate_left_accumulator(true),
-            0x18 => self.jump_if_immediate_8(true),
+            0x18 => self.jump_if_immediate_8(bus, true),
             0x19 => self.add_16(Register16::HL, Register16::DE),
-            0x1A => self.load_8_from(Register8::A, Register16::DE),
+            0x1A => self.load_8_from(bus, Register8::A, Register16::DE),
             0x1B => self.dec_16(Register16::DE),
             0x1C => self.inc_8(Register8::E),
             0x1D => self.dec_8(Register8::E),
-            0x1E => self.load_8_immediate(Register8::E),
+            0x1E => self.load_8_immediate(bus, Register8::E),
             0x1F => self.rotate_right_accumulator(true),
 
             // Opcodes 2x
-            0x20 => self.jump_if_immediate_8(!self.registers.get_zero_flag()),
-            0x21 => self.load_16_immediate(Register16::HL),
-            0x22 => self.load_8_at_increment(Register16::HL, Register8::A),
+            0x20 => self.jump_if_immediate_8(bus, !self.registers.get_zero_flag()),
+            0x21 => self.load_16_immediate(bus, Register16::HL),
+            0x22 => self.load_8_at_increment(bus, Register16::HL, Register8::A),
             0x23 => self.inc_16(Register16::HL),
             0x24 => self.inc_8(Register8::H),
             0x25 => self.dec_8(Register8::H),
-            0x26 => self.load_8_immediate(Register8::H),
+            0x26 => self.load_8_immediate(bus, Register8::H),
             0x27 => self.decimal_adjust(),
-            0x28 => self.jump_if_immediate_8(self.registers.get_zero_flag()),
+            0x28 => self.jump_if_immediate_8(bus, self.registers.get_zero_flag()),
             0x29 => self.add_16(Register16::HL, Register16::HL),
-            0x2A => self.load_8_from_increment(Register8::A, Register16::HL),
+            0x2A => self.load_8_from_increment(bus, Register8::A, Register16::HL),
             0x2B => self.dec_16(Register16::HL),
             0x2C => self.inc_8(Register8::L),
             0x2D => self.dec_8(Register8::L),
-            0x2E => self.load_8_immediate(Register8::L),
+            0x2E => self.load_8_immediate(bus, Register8::L),
             0x2F => self.complement(),
 
             // Opcodes 3x
-            0x30 => self.jump_if_immediate_8(!self.registers.get_carry_flag()),
-            0x31 => self.load_16_immediate(Register16::SP),
-            0x32 => self.load_8_at_decrement(Register16::HL, Register8::A),
+            0x30 => self.jump_if_immediate_8(bus, !self.registers.get_carry_flag()),
+            0x31 => self.load_16_immediate(bus, Register16::SP),
+            0x32 => self.load_8_at_decrement(bus, Register16::HL, Register8::A),
             0x33 => self.inc_16(Register16::SP),
-            0x34 => self.inc_8_at(Register16::HL),
-            0x35 => self.dec_8_at(Register16::HL),
-            0x36 => self.load_8_immediate_at(Register16::HL),
+            0x34 => self.inc_8_at(bus, Register16::HL),
+            0x35 => self.dec_8_at(bus, Register16::HL),
+            0x36 => self.load_8_immediate_at(bus, Register16::HL),
             0x37 => self.set_carry_flag(),
-            0x38 => self.jump_if_immediate_8(self.registers.get_carry_flag()),
+            0x38 => self.jump_if_immediate_8(bus, self.registers.get_carry_flag()),
             0x39 => self.add_16(Register16::HL, Register16::SP),
-            0x3A => self.load_8_from_decrement(Register8::A, Register16::HL),
+            0x3A => self.load_8_from_decrement(bus, Register8::A, Register16::HL),
             0x3B => self.dec_16(Register16::SP),
             0x3C => self.inc_8(Register8::A),
             0x3D => self.dec_8(Register8::A),
-            0x3E => self.load_8_immediate(Register8::A),
+            0x3E => self.load_8_immediate(bus, Register8::A),
             0x3F => self.complement_carry_flag(),
 
             // Opcodes 4x
@@ -343,7 +425,7 @@ impl LR35902 {
             0x43 => self.load_8(Register8::B, Register8::E),
             0x44 => self.load_8(Register8::B, Register8::H),
             0x45 => self.load_8(Register8::B, Register8::L),
-            0x46 => self.load_8_from(Register8::B, Register16::HL),
+            0x46 => self.load_8_from(bus, Register8::B, Register16::HL),
             0x47 => self.load_8(Register8::B, Register8::A),
             0x48 => self.load_8(Register8::C, Register8::B),
             0x49 => self.load_8(Register8::C, Register8::C),
@@ -351,7 +433,7 @@ impl LR35902 {
             0x4B => self.load_8(Register8::C, Register8::E),
             0x4C => self.load_8(Register8::C, Register8::H),
             0x4D => self.load_8(Register8::C, Register8::L),
-            0x4E => self.load_8_from(Register8::C, Register16::HL),
+            0x4E => self.load_8_from(bus, Register8::C, Register16::HL),
             0x4F => self.load_8(Register8::C, Register8::A),
 
             // Opcodes 5x
@@ -361,7 +443,7 @@ impl LR35902 {
             0x53 => self.load_8(Register8::D, Register8::E),
             0x54 => self.load_8(Register8::D, Register8::H),
             0x55 => self.load_8(Register8::D, Register8::L),
-            0x56 => self.load_8_from(Register8::D, Register16::HL),
+            0x56 => self.load_8_from(bus, Register8::D, Register16::HL),
             0x57 => self.load_8(Register8::D, Register8::A),
             0x58 => self.load_8(Register8::E, Register8::B),
             0x59 => self.load_8(Register8::E, Register8::C),
@@ -369,7 +451,7 @@ impl LR35902 {
             0x5B => self.load_8(Register8::E, Register8::E),
             0x5C => self.load_8(Register8::E, Register8::H),
             0x5D => self.load_8(Register8::E, Register8::L),
-            0x5E => self.load_8_from(Register8::E, Register16::HL),
+            0x5E => self.load_8_from(bus, Register8::E, Register16::HL),
             0x5F => self.load_8(Register8::E, Register8::A),
 
             // Opcodes 6x
@@ -379,7 +461,7 @@ impl LR35902 {
             0x63 => self.load_8(Register8::H, Register8::E),
             0x64 => self.load_8(Register8::H, Register8::H),
             0x65 => self.load_8(Register8::H, Register8::L),
-            0x66 => self.load_8_from(Register8::H, Register16::HL),
+            0x66 => self.load_8_from(bus, Register8::H, Register16::HL),
             0x67 => self.load_8(Register8::H, Register8::A),
             0x68 => self.load_8(Register8::L, Register8::B),
             0x69 => self.load_8(Register8::L, Register8::C),
@@ -387,25 +469,25 @@ impl LR35902 {
             0x6B => self.load_8(Register8::L, Register8::E),
             0x6C => self.load_8(Register8::L, Register8::H),
             0x6D => self.load_8(Register8::L, Register8::L),
-            0x6E => self.load_8_from(Register8::L, Register16::HL),
+            0x6E => self.load_8_from(bus, Register8::L, Register16::HL),
             0x6F => self.load_8(Register8::L, Register8::A),
 
             // Opcodes 7x
-            0x70 => self.load_8_at(Register16::HL, Register8::B),
-            0x71 => self.load_8_at(Register16::HL, Register8::C),
-            0x72 => self.load_8_at(Register16::HL, Register8::D),
-            0x73 => self.load_8_at(Register16::HL, Register8::E),
-            0x74 => self.load_8_at(Register16::HL, Register8::H),
-            0x75 => self.load_8_at(Register16::HL, Register8::L),
-            0x76 => self.halt(),
-            0x77 => self.load_8_at(Register16::HL, Register8::A),
+            0x70 => self.load_8_at(bus, Register16::HL, Register8::B),
+            0x71 => self.load_8_at(bus, Register16::HL, Register8::C),
+            0x72 => self.load_8_at(bus, Register16::HL, Register8::D),
+            0x73 => self.load_8_at(bus, Register16::HL, Register8::E),
+            0x74 => self.load_8_at(bus, Register16::HL, Register8::H),
+            0x75 => self.load_8_at(bus, Register16::HL, Register8::L),
+            0x76 => self.halt(bus),
+            0x77 => self.load_8_at(bus, Register16::HL, Register8::A),
             0x78 => self.load_8(Register8::A, Register8::B),
             0x79 => self.load_8(Register8::A, Register8::C),
             0x7A => self.load_8(Register8::A, Register8::D),
             0x7B => self.load_8(Register8::A, Register8::E),
             0x7C => self.load_8(Register8::A, Register8::H),
             0x7D => self.load_8(Register8::A, Register8::L),
-            0x7E => self.load_8_from(Register8::A, Register16::HL),
+            0x7E => self.load_8_from(bus, Register8::A, Register16::HL),
             0x7F => self.load_8(Register8::A, Register8::A),
 
             // Opcodes 8x
@@ -415,7 +497,7 @@ impl LR35902 {
             0x83 => self.add_8(Register8::E),
             0x84 => self.add_8(Register8::H),
             0x85 => self.add_8(Register8::L),
-            0x86 => self.add_8_from(Register16::HL),
+            0x86 => self.add_8_from(bus, Register16::HL),
             0x87 => self.add_8(Register8::A),
             0x88 => self.add_carry_8(Register8::B),
             0x89 => self.add_carry_8(Register8::C),
@@ -423,7 +505,7 @@ impl LR35902 {
             0x8B => self.add_carry_8(Register8::E),
             0x8C => self.add_carry_8(Register8::H),
             0x8D => self.add_carry_8(Register8::L),
-            0x8E => self.add_carry_8_from(Register16::HL),
+            0x8E => self.add_carry_8_from(bus, Register16::HL),
             0x8F => self.add_carry_8(Register8::A),
 
             // Opcodes 9x
@@ -433,7 +515,7 @@ impl LR35902 {
             0x93 => self.sub_8(Register8::E),
             0x94 => self.sub_8(Register8::H),
             0x95 => self.sub_8(Register8::L),
-            0x96 => self.sub_8_from(Register16::HL),
+            0x96 => self.sub_8_from(bus, Register16::HL),
             0x97 => self.sub_8(Register8::A),
             0x98 => self.sub_carry_8(Register8::B),
             0x99 => self.sub_carry_8(Register8::C),
@@ -441,7 +523,7 @@ impl LR35902 {
             0x9B => self.sub_carry_8(Register8::E),
             0x9C => self.sub_carry_8(Register8::H),
             0x9D => self.sub_carry_8(Register8::L),
-            0x9E => self.sub_carry_8_from(Register16::HL),
+            0x9E => self.sub_carry_8_from(bus, Register16::HL),
             0x9F => self.sub_carry_8(Register8::A),
 
             // Opcodes Ax
@@ -451,7 +533,7 @@ impl LR35902 {
             0xA3 => self.and_8(Register8::E),
             0xA4 => self.and_8(Register8::H),
             0xA5 => self.and_8(Register8::L),
-            0xA6 => self.and_8_from(Register16::HL),
+            0xA6 => self.and_8_from(bus, Register16::HL),
             0xA7 => self.and_8(Register8::A),
             0xA8 => self.xor_8(Register8::B),
             0xA9 => self.xor_8(Register8::C),
@@ -459,7 +541,7 @@ impl LR35902 {
             0xAB => self.xor_8(Register8::E),
             0xAC => self.xor_8(Register8::H),
             0xAD => self.xor_8(Register8::L),
-            0xAE => self.xor_8_from(Register16::HL),
+            0xAE => self.xor_8_from(bus, Register16::HL),
             0xAF => self.xor_8(Register8::A),
 
             // Opcodes Bx
@@ -469,7 +551,7 @@ impl LR35902 {
             0xB3 => self.or_8(Register8::E),
             0xB4 => self.or_8(Register8::H),
             0xB5 => self.or_8(Register8::L),
-            0xB6 => self.or_8_from(Register16::HL),
+            0xB6 => self.or_8_from(bus, Register16::HL),
             0xB7 => self.or_8(Register8::A),
             0xB8 => self.cp_8(Register8::B),
             0xB9 => self.cp_8(Register8::C),
@@ -477,85 +559,85 @@ impl LR35902 {
             0xBB => self.cp_8(Register8::E),
             0xBC => self.cp_8(Register8::H),
             0xBD => self.cp_8(Register8::L),
-            0xBE => self.cp_8_from(Register16::HL),
+            0xBE => self.cp_8_from(bus, Register16::HL),
             0xBF => self.cp_8(Register8::A),
 
             // Opcodes Cx
-            0xC0 => self.ret_if(!self.registers.get_zero_flag()),
-            0xC1 => self.pop(Register16::BC),
-            0xC2 => self.jump_if_immediate_16(!self.registers.get_zero_flag()),
-            0xC3 => self.jump_if_immediate_16(true),
-            0xC4 => self.call(!self.registers.get_zero_flag()),
-            0xC5 => self.push(Register16::BC),
-            0xC6 => self.add_8_immediate(),
-            0xC7 => self.call_vec(0x00u16),
-            0xC8 => self.ret_if(self.registers.get_zero_flag()),
-            0xC9 => self.ret(),
-            0xCA => self.jump_if_immediate_16(self.registers.get_zero_flag()),
-            0xCB => self.prefix_cb(),
-            0xCC => self.call(self.registers.get_zero_flag()),
-            0xCD => self.call(true),
-            0xCE => self.add_carry_8_immediate(),
-            0xCF => self.call_vec(0x08u16),
+            0xC0 => self.ret_if(bus, !self.registers.get_zero_flag()),
+            0xC1 => self.pop(bus, Register16::BC),
+            0xC2 => self.jump_if_immediate_16(bus, !self.registers.get_zero_flag()),
+            0xC3 => self.jump_if_immediate_16(bus, true),
+            0xC4 => self.call(bus, !self.registers.get_zero_flag()),
+            0xC5 => self.push(bus, Register16::BC),
+            0xC6 => self.add_8_immediate(bus),
+            0xC7 => self.call_vec(bus, 0x00u16),
+            0xC8 => self.ret_if(bus, self.registers.get_zero_flag()),
+            0xC9 => self.ret(bus),
+            0xCA => self.jump_if_immediate_16(bus, self.registers.get_zero_flag()),
+            0xCB => self.prefix_cb(bus),
+            0xCC => self.call(bus, self.registers.get_zero_flag()),
+            0xCD => self.call(bus, true),
+            0xCE => self.add_carry_8_immediate(bus),
+            0xCF => self.call_vec(bus, 0x08u16),
 
             // Opcodes Dx
-            0xD0 => self.ret_if(!self.registers.get_carry_flag()),
-            0xD1 => self.pop(Register16::DE),
-            0xD2 => self.jump_if_immediate_16(!self.registers.get_carry_flag()),
-            0xD3 => unreachable!(),
-            0xD4 => self.call(!self.registers.get_carry_flag()),
-            0xD5 => self.push(Register16::DE),
-            0xD6 => self.sub_8_immediate(),
-            0xD7 => self.call_vec(0x10u16),
-            0xD8 => self.ret_if(self.registers.get_carry_flag()),
-            0xD9 => self.ret_interrupt(),
-            0xDA => self.jump_if_immediate_16(self.registers.get_carry_flag()),
-            0xDB => unreachable!(),
-            0xDC => self.call(self.registers.get_carry_flag()),
-            0xDD => unreachable!(),
-            0xDE => self.sub_carry_8_immediate(),
-            0xDF => self.call_vec(0x18u16),
+            0xD0 => self.ret_if(bus, !self.registers.get_carry_flag()),
+            0xD1 => self.pop(bus, Register16::DE),
+            0xD2 => self.jump_if_immediate_16(bus, !self.registers.get_carry_flag()),
+            0xD3 => self.lock_up(opcode),
+            0xD4 => self.call(bus, !self.registers.get_carry_flag()),
+            0xD5 => self.push(bus, Register16::DE),
+            0xD6 => self.sub_8_immediate(bus),
+            0xD7 => self.call_vec(bus, 0x10u16),
+            0xD8 => self.ret_if(bus, self.registers.get_carry_flag()),
+            0xD9 => self.ret_interrupt(bus),
+            0xDA => self.jump_if_immediate_16(bus, self.registers.get_carry_flag()),
+            0xDB => self.lock_up(opcode),
+            0xDC => self.call(bus, self.registers.get_carry_flag()),
+            0xDD => self.lock_up(opcode),
+            0xDE => self.sub_carry_8_immediate(bus),
+            0xDF => self.call_vec(bus, 0x18u16),
 
             // Opcodes Ex
-            0xE0 => self.load_8_at_io_immediate(Register8::A),
-            0xE1 => self.pop(Register16::HL),
-            0xE2 => self.load_8_at_io(Register8::C, Register8::A),
-            0xE3 => unreachable!(),
-            0xE4 => unreachable!(),
-            0xE5 => self.push(Register16::HL),
-            0xE6 => self.and_8_immediate(),
-            0xE7 => self.call_vec(0x20u16),
-            0xE8 => self.add_16_immediate(Register16::SP),
+            0xE0 => self.load_8_at_io_immediate(bus, Register8::A),
+            0xE1 => self.pop(bus, Register16::HL),
+            0xE2 => self.load_8_at_io(bus, Register8::C, Register8::A),
+            0xE3 => self.lock_up(opcode),
+            0xE4 => self.lock_up(opcode),
+            0xE5 => self.push(bus, Register16::HL),
+            0xE6 => self.and_8_immediate(bus),
+            0xE7 => self.call_vec(bus, 0x20u16),
+            0xE8 => self.add_16_immediate(bus, Register16::SP),
             0xE9 => self.jump(Register16::HL),
-            0xEA => self.load_8_at_immediate(Register8::A),
-            0xEB => unreachable!(),
-            0xEC => unreachable!(),
-            0xED => unreachable!(),
-            0xEE => self.xor_8_immediate(),
-            0xEF => self.call_vec(0x28u16),
+            0xEA => self.load_8_at_immediate(bus, Register8::A),
+            0xEB => self.lock_up(opcode),
+            0xEC => self.lock_up(opcode),
+            0xED => self.lock_up(opcode),
+            0xEE => self.xor_8_immediate(bus),
+            0xEF => self.call_vec(bus, 0x28u16),
 
             // Opcodes Fx
-            0xF0 => self.load_8_from_io_immediate(Register8::A),
-            0xF1 => self.pop(Register16::AF),
-            0xF2 => self.load_8_from_io(Register8::C, Register8::A),
+            0xF0 => self.load_8_from_io_immediate(bus, Register8::A),
+            0xF1 => self.pop(bus, Register16::AF),
+            0xF2 => self.load_8_from_io(bus, Register8::C, Register8::A),
             0xF3 => self.disable_interrupts(),
-            0xF4 => unreachable!(),
-            0xF5 => self.push(Register16::AF),
-            0xF6 => self.or_8_immediate(),
-            0xF7 => self.call_vec(0x30u16),
-            0xF8 => self.load_16_add_immediate(Register16::HL, Register16::SP),
+            0xF4 => self.lock_up(opcode),
+            0xF5 => self.push(bus, Register16::AF),
+            0xF6 => self.or_8_immediate(bus),
+            0xF7 => self.call_vec(bus, 0x30u16),
+            0xF8 => self.load_16_add_immediate(bus, Register16::HL, Register16::SP),
             0xF9 => self.load_16(Register16::SP, Register16::HL),
-            0xFA => self.load_8_from_immediate(Register8::A),
+            0xFA => self.load_8_from_immediate(bus, Register8::A),
             0xFB => self.enable_interrupts(),
-            0xFC => unreachable!(),
-            0xFD => unreachable!(),
-            0xFE => self.cp_8_immediate(),
-            0xFF => self.call_vec(0x38u16),
+            0xFC => self.lock_up(opcode),
+            0xFD => self.lock_up(opcode),
+            0xFE => self.cp_8_immediate(bus),
+            0xFF => self.call_vec(bus, 0x38u16),
         }
     }
 
-    fn prefix_cb(&mut self) -> usize {
-        let opcode = self.pc_next_8();
+    fn prefix_cb(&mut self, bus: &mut impl Bus) -> usize {
+        let opcode = self.pc_next_8(bus);
 
         match opcode {
             // Opcodes 0x
@@ -565,7 +647,7 @@ impl LR35902 {
             0x03 => self.rotate_left(Register8::E),
             0x04 => self.rotate_left(Register8::H),
             0x05 => self.rotate_left(Register8::L),
-            0x06 => self.rotate_left_at(Register16::HL),
+            0x06 => self.rotate_left_at(bus, Register16::HL),
             0x07 => self.rotate_left(Register8::A),
             0x08 => self.rotate_right(Register8::B),
             0x09 => self.rotate_right(Register8::C),
@@ -573,7 +655,7 @@ impl LR35902 {
             0x0B => self.rotate_right(Register8::E),
             0x0C => self.rotate_right(Register8::H),
             0x0D => self.rotate_right(Register8::L),
-            0x0E => self.rotate_right_at(Register16::HL),
+            0x0E => self.rotate_right_at(bus, Register16::HL),
             0x0F => self.rotate_right(Register8::A),
 
             // Opcodes 1x
@@ -583,7 +665,7 @@ impl LR35902 {
             0x13 => self.rotate_left_carry(Register8::E),
             0x14 => self.rotate_left_carry(Register8::H),
             0x15 => self.rotate_left_carry(Register8::L),
-            0x16 => self.rotate_left_carry_at(Register16::HL),
+            0x16 => self.rotate_left_carry_at(bus, Register16::HL),
             0x17 => self.rotate_left_carry(Register8::A),
             0x18 => self.rotate_right_carry(Register8::B),
             0x19 => self.rotate_right_carry(Register8::C),
@@ -591,7 +673,7 @@ impl LR35902 {
             0x1B => self.rotate_right_carry(Register8::E),
             0x1C => self.rotate_right_carry(Register8::H),
             0x1D => self.rotate_right_carry(Register8::L),
-            0x1E => self.rotate_right_carry_at(Register16::HL),
+            0x1E => self.rotate_right_carry_at(bus, Register16::HL),
             0x1F => self.rotate_right_carry(Register8::A),
 
             // Opcodes 2x
@@ -601,7 +683,7 @@ impl LR35902 {
             0x23 => self.shift_left(Register8::E),
             0x24 => self.shift_left(Register8::H),
             0x25 => self.shift_left(Register8::L),
-            0x26 => self.shift_left_at(Register16::HL),
+            0x26 => self.shift_left_at(bus, Register16::HL),
             0x27 => self.shift_left(Register8::A),
             0x28 => self.shift_right(Register8::B),
             0x29 => self.shift_right(Register8::C),
@@ -609,7 +691,7 @@ impl LR35902 {
             0x2B => self.shift_right(Register8::E),
             0x2C => self.shift_right(Register8::H),
             0x2D => self.shift_right(Register8::L),
-            0x2E => self.shift_right_at(Register16::HL),
+            0x2E => self.shift_right_at(bus, Register16::HL),
             0x2F => self.shift_right(Register8::A),
 
             // Opcodes 3x
@@ -619,7 +701,7 @@ impl LR35902 {
             0x33 => self.swap(Register8::E),
             0x34 => self.swap(Register8::H),
             0x35 => self.swap(Register8::L),
-            0x36 => self.swap_at(Register16::HL),
+            0x36 => self.swap_at(bus, Register16::HL),
             0x37 => self.swap(Register8::A),
             0x38 => self.shift_right_logic(Register8::B),
             0x39 => self.shift_right_logic(Register8::C),
@@ -627,7 +709,7 @@ impl LR35902 {
             0x3B => self.shift_right_logic(Register8::E),
             0x3C => self.shift_right_logic(Register8::H),
             0x3D => self.shift_right_logic(Register8::L),
-            0x3E => self.shift_right_logic_at(Register16::HL),
+            0x3E => self.shift_right_logic_at(bus, Register16::HL),
             0x3F => self.shift_right_logic(Register8::A),
 
             // Opcodes 4x
@@ -637,7 +719,7 @@ impl LR35902 {
             0x43 => self.bit(0, Register8::E),
             0x44 => self.bit(0, Register8::H),
             0x45 => self.bit(0, Register8::L),
-            0x46 => self.bit_at(0, Register16::HL),
+            0x46 => self.bit_at(bus, 0, Register16::HL),
             0x47 => self.bit(0, Register8::A),
             0x48 => self.bit(1, Register8::B),
             0x49 => self.bit(1, Register8::C),
@@ -645,7 +727,7 @@ impl LR35902 {
             0x4B => self.bit(1, Register8::E),
             0x4C => self.bit(1, Register8::H),
             0x4D => self.bit(1, Register8::L),
-            0x4E => self.bit_at(1, Register16::HL),
+            0x4E => self.bit_at(bus, 1, Register16::HL),
             0x4F => self.bit(1, Register8::A),
 
             // Opcodes 5x
@@ -655,7 +737,7 @@ impl LR35902 {
             0x53 => self.bit(2, Register8::E),
             0x54 => self.bit(2, Register8::H),
             0x55 => self.bit(2, Register8::L),
-            0x56 => self.bit_at(2, Register16::HL),
+            0x56 => self.bit_at(bus, 2, Register16::HL),
             0x57 => self.bit(2, Register8::A),
             0x58 => self.bit(3, Register8::B),
             0x59 => self.bit(3, Register8::C),
@@ -663,7 +745,7 @@ impl LR35902 {
             0x5B => self.bit(3, Register8::E),
             0x5C => self.bit(3, Register8::H),
             0x5D => self.bit(3, Register8::L),
-            0x5E => self.bit_at(3, Register16::HL),
+            0x5E => self.bit_at(bus, 3, Register16::HL),
             0x5F => self.bit(3, Register8::A),
 
             // Opcodes 6x
@@ -673,7 +755,7 @@ impl LR35902 {
             0x63 => self.bit(4, Register8::E),
             0x64 => self.bit(4, Register8::H),
             0x65 => self.bit(4, Register8::L),
-            0x66 => self.bit_at(4, Register16::HL),
+            0x66 => self.bit_at(bus, 4, Register16::HL),
             0x67 => self.bit(4, Register8::A),
             0x68 => self.bit(5, Register8::B),
             0x69 => self.bit(5, Register8::C),
@@ -681,7 +763,7 @@ impl LR35902 {
             0x6B => self.bit(5, Register8::E),
             0x6C => self.bit(5, Register8::H),
             0x6D => self.bit(5, Register8::L),
-            0x6E => self.bit_at(5, Register16::HL),
+            0x6E => self.bit_at(bus, 5, Register16::HL),
             0x6F => self.bit(5, Register8::A),
 
             // Opcodes 7x
@@ -691,7 +773,7 @@ impl LR35902 {
             0x73 => self.bit(6, Register8::E),
             0x74 => self.bit(6, Register8::H),
             0x75 => self.bit(6, Register8::L),
-            0x76 => self.bit_at(6, Register16::HL),
+            0x76 => self.bit_at(bus, 6, Register16::HL),
             0x77 => self.bit(6, Register8::A),
             0x78 => self.bit(7, Register8::B),
             0x79 => self.bit(7, Register8::C),
@@ -699,7 +781,7 @@ impl LR35902 {
             0x7B => self.bit(7, Register8::E),
             0x7C => self.bit(7, Register8::H),
             0x7D => self.bit(7, Register8::L),
-            0x7E => self.bit_at(7, Register16::HL),
+            0x7E => self.bit_at(bus, 7, Register16::HL),
             0x7F => self.bit(7, Register8::A),
 
             // Opcodes 8x
@@ -709,7 +791,7 @@ impl LR35902 {
             0x83 => self.reset_bit(0, Register8::E),
             0x84 => self.reset_bit(0, Register8::H),
             0x85 => self.reset_bit(0, Register8::L),
-            0x86 => self.reset_bit_at(0, Register16::HL),
+            0x86 => self.reset_bit_at(bus, 0, Register16::HL),
             0x87 => self.reset_bit(0, Register8::A),
             0x88 => self.reset_bit(1, Register8::B),
             0x89 => self.reset_bit(1, Register8::C),
@@ -717,7 +799,7 @@ impl LR35902 {
             0x8B => self.reset_bit(1, Register8::E),
             0x8C => self.reset_bit(1, Register8::H),
             0x8D => self.reset_bit(1, Register8::L),
-            0x8E => self.reset_bit_at(1, Register16::HL),
+            0x8E => self.reset_bit_at(bus, 1, Register16::HL),
             0x8F => self.reset_bit(1, Register8::A),
 
             // Opcodes 9x
@@ -727,7 +809,7 @@ impl LR35902 {
             0x93 => self.reset_bit(2, Register8::E),
             0x94 => self.reset_bit(2, Register8::H),
             0x95 => self.reset_bit(2, Register8::L),
-            0x96 => self.reset_bit_at(2, Register16::HL),
+            0x96 => self.reset_bit_at(bus, 2, Register16::HL),
             0x97 => self.reset_bit(2, Register8::A),
             0x98 => self.reset_bit(3, Register8::B),
             0x99 => self.reset_bit(3, Register8::C),
@@ -735,7 +817,7 @@ impl LR35902 {
             0x9B => self.reset_bit(3, Register8::E),
             0x9C => self.reset_bit(3, Register8::H),
             0x9D => self.reset_bit(3, Register8::L),
-            0x9E => self.reset_bit_at(3, Register16::HL),
+            0x9E => self.reset_bit_at(bus, 3, Register16::HL),
             0x9F => self.reset_bit(3, Register8::A),
 
             // Opcodes Ax
@@ -745,7 +827,7 @@ impl LR35902 {
             0xA3 => self.reset_bit(4, Register8::E),
             0xA4 => self.reset_bit(4, Register8::H),
             0xA5 => self.reset_bit(4, Register8::L),
-            0xA6 => self.reset_bit_at(4, Register16::HL),
+            0xA6 => self.reset_bit_at(bus, 4, Register16::HL),
             0xA7 => self.reset_bit(4, Register8::A),
             0xA8 => self.reset_bit(5, Register8::B),
             0xA9 => self.reset_bit(5, Register8::C),
@@ -753,7 +835,7 @@ impl LR35902 {
             0xAB => self.reset_bit(5, Register8::E),
             0xAC => self.reset_bit(5, Register8::H),
             0xAD => self.reset_bit(5, Register8::L),
-            0xAE => self.reset_bit_at(5, Register16::HL),
+            0xAE => self.reset_bit_at(bus, 5, Register16::HL),
             0xAF => self.reset_bit(5, Register8::A),
 
             // Opcodes Bx
@@ -763,7 +845,7 @@ impl LR35902 {
             0xB3 => self.reset_bit(6, Register8::E),
             0xB4 => self.reset_bit(6, Register8::H),
             0xB5 => self.reset_bit(6, Register8::L),
-            0xB6 => self.reset_bit_at(6, Register16::HL),
+            0xB6 => self.reset_bit_at(bus, 6, Register16::HL),
             0xB7 => self.reset_bit(6, Register8::A),
             0xB8 => self.reset_bit(7, Register8::B),
             0xB9 => self.reset_bit(7, Register8::C),
@@ -771,7 +853,7 @@ impl LR35902 {
             0xBB => self.reset_bit(7, Register8::E),
             0xBC => self.reset_bit(7, Register8::H),
             0xBD => self.reset_bit(7, Register8::L),
-            0xBE => self.reset_bit_at(7, Register16::HL),
+            0xBE => self.reset_bit_at(bus, 7, Register16::HL),
             0xBF => self.reset_bit(7, Register8::A),
 
             // Opcodes Cx
@@ -781,7 +863,7 @@ impl LR35902 {
             0xC3 => self.set_bit(0, Register8::E),
             0xC4 => self.set_bit(0, Register8::H),
             0xC5 => self.set_bit(0, Register8::L),
-            0xC6 => self.set_bit_at(0, Register16::HL),
+            0xC6 => self.set_bit_at(bus, 0, Register16::HL),
             0xC7 => self.set_bit(0, Register8::A),
             0xC8 => self.set_bit(1, Register8::B),
             0xC9 => self.set_bit(1, Register8::C),
@@ -789,7 +871,7 @@ impl LR35902 {
             0xCB => self.set_bit(1, Register8::E),
             0xCC => self.set_bit(1, Register8::H),
             0xCD => self.set_bit(1, Register8::L),
-            0xCE => self.set_bit_at(1, Register16::HL),
+            0xCE => self.set_bit_at(bus, 1, Register16::HL),
             0xCF => self.set_bit(1, Register8::A),
 
             // Opcodes Dx
@@ -799,7 +881,7 @@ impl LR35902 {
             0xD3 => self.set_bit(2, Register8::E),
             0xD4 => self.set_bit(2, Register8::H),
             0xD5 => self.set_bit(2, Register8::L),
-            0xD6 => self.set_bit_at(2, Register16::HL),
+            0xD6 => self.set_bit_at(bus, 2, Register16::HL),
             0xD7 => self.set_bit(2, Register8::A),
             0xD8 => self.set_bit(3, Register8::B),
             0xD9 => self.set_bit(3, Register8::C),
@@ -807,7 +889,7 @@ impl LR35902 {
             0xDB => self.set_bit(3, Register8::E),
             0xDC => self.set_bit(3, Register8::H),
             0xDD => self.set_bit(3, Register8::L),
-            0xDE => self.set_bit_at(3, Register16::HL),
+            0xDE => self.set_bit_at(bus, 3, Register16::HL),
             0xDF => self.set_bit(3, Register8::A),
 
             // Opcodes Ex
@@ -817,7 +899,7 @@ impl LR35902 {
             0xE3 => self.set_bit(4, Register8::E),
             0xE4 => self.set_bit(4, Register8::H),
             0xE5 => self.set_bit(4, Register8::L),
-            0xE6 => self.set_bit_at(4, Register16::HL),
+            0xE6 => self.set_bit_at(bus, 4, Register16::HL),
             0xE7 => self.set_bit(4, Register8::A),
             0xE8 => self.set_bit(5, Register8::B),
             0xE9 => self.set_bit(5, Register8::C),
@@ -825,7 +907,7 @@ impl LR35902 {
             0xEB => self.set_bit(5, Register8::E),
             0xEC => self.set_bit(5, Register8::H),
             0xED => self.set_bit(5, Register8::L),
-            0xEE => self.set_bit_at(5, Register16::HL),
+            0xEE => self.set_bit_at(bus, 5, Register16::HL),
             0xEF => self.set_bit(5, Register8::A),
 
             // Opcodes Fx
@@ -835,7 +917,7 @@ impl LR35902 {
             0xF3 => self.set_bit(6, Register8::E),
             0xF4 => self.set_bit(6, Register8::H),
             0xF5 => self.set_bit(6, Register8::L),
-            0xF6 => self.set_bit_at(6, Register16::HL),
+            0xF6 => self.set_bit_at(bus, 6, Register16::HL),
             0xF7 => self.set_bit(6, Register8::A),
             0xF8 => self.set_bit(7, Register8::B),
             0xF9 => self.set_bit(7, Register8::C),
@@ -843,20 +925,48 @@ impl LR35902 {
             0xFB => self.set_bit(7, Register8::E),
             0xFC => self.set_bit(7, Register8::H),
             0xFD => self.set_bit(7, Register8::L),
-            0xFE => self.set_bit_at(7, Register16::HL),
+            0xFE => self.set_bit_at(bus, 7, Register16::HL),
             0xFF => self.set_bit(7, Register8::A),
         }
     }
 
-    fn pc_next_8(&mut self) -> u8 {
-        let result = self.mmu.borrow().read_8(self.registers.pc);
-        self.registers.pc += 1;
+    fn read_8(&mut self, bus: &mut impl Bus, address: u16) -> u8 {
+        self.cycles += 4;
+        bus.read_8(address)
+    }
+
+    fn write_8(&mut self, bus: &mut impl Bus, address: u16, value: u8) {
+        self.cycles += 4;
+        bus.write_8(address, value);
+    }
+
+    fn read_16(&mut self, bus: &mut impl Bus, address: u16) -> u16 {
+        let low = self.read_8(bus, address);
+        let high = self.read_8(bus, address.wrapping_add(1));
+        u16::from_le_bytes([low, high])
+    }
+
+    fn write_16(&mut self, bus: &mut impl Bus, address: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_8(bus, address, low);
+        self.write_8(bus, address.wrapping_add(1), high);
+    }
+
+    /// Internal cycle without memory access.
+    fn idle(&mut self, bus: &mut impl Bus) {
+        self.cycles += 4;
+        bus.idle();
+    }
+
+    fn pc_next_8(&mut self, bus: &mut impl Bus) -> u8 {
+        let result = self.read_8(bus, self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         result
     }
 
-    fn pc_next_16(&mut self) -> u16 {
-        let result = self.mmu.borrow().read_16(self.registers.pc);
-        self.registers.pc += 2;
+    fn pc_next_16(&mut self, bus: &mut impl Bus) -> u16 {
+        let result = self.read_16(bus, self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(2);
         result
     }
 
@@ -871,100 +981,144 @@ impl LR35902 {
         4
     }
 
-    fn load_8_at(&mut self, destination: Register16, source: Register8) -> usize {
+    fn load_8_at(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register16,
+        source: Register8,
+    ) -> usize {
         let address = self.registers.get_16(destination);
         let value = self.registers.get_8(source);
-        self.mmu.borrow_mut().write_8(address, value);
+        self.write_8(bus, address, value);
         8
     }
 
-    fn load_8_at_increment(&mut self, destination: Register16, source: Register8) -> usize {
-        self.load_8_at(destination, source);
-        self.registers
-            .set_16(destination, self.registers.get_16(destination) + 1);
+    fn load_8_at_increment(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register16,
+        source: Register8,
+    ) -> usize {
+        self.load_8_at(bus, destination, source);
+        self.registers.set_16(
+            destination,
+            self.registers.get_16(destination).wrapping_add(1),
+        );
         8
     }
 
-    fn load_8_at_decrement(&mut self, destination: Register16, source: Register8) -> usize {
-        self.load_8_at(destination, source);
-        self.registers
-            .set_16(destination, self.registers.get_16(destination) - 1);
+    fn load_8_at_decrement(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register16,
+        source: Register8,
+    ) -> usize {
+        self.load_8_at(bus, destination, source);
+        self.registers.set_16(
+            destination,
+            self.registers.get_16(destination).wrapping_sub(1),
+        );
         8
     }
 
-    fn load_8_from(&mut self, destination: Register8, source: Register16) -> usize {
+    fn load_8_from(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register8,
+        source: Register16,
+    ) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         self.registers.set_8(destination, value);
         8
     }
 
-    fn load_8_from_increment(&mut self, destination: Register8, source: Register16) -> usize {
-        self.load_8_from(destination, source);
+    fn load_8_from_increment(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register8,
+        source: Register16,
+    ) -> usize {
+        self.load_8_from(bus, destination, source);
         self.registers
             .set_16(source, self.registers.get_16(source).wrapping_add(1));
         8
     }
 
-    fn load_8_from_decrement(&mut self, destination: Register8, source: Register16) -> usize {
-        self.load_8_from(destination, source);
+    fn load_8_from_decrement(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register8,
+        source: Register16,
+    ) -> usize {
+        self.load_8_from(bus, destination, source);
         self.registers
             .set_16(source, self.registers.get_16(source).wrapping_sub(1));
         8
     }
 
-    fn load_8_immediate(&mut self, destination: Register8) -> usize {
-        let value = self.pc_next_8();
+    fn load_8_immediate(&mut self, bus: &mut impl Bus, destination: Register8) -> usize {
+        let value = self.pc_next_8(bus);
         self.registers.set_8(destination, value);
         8
     }
 
-    fn load_8_immediate_at(&mut self, destination: Register16) -> usize {
+    fn load_8_immediate_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.pc_next_8();
-        self.mmu.borrow_mut().write_8(address, value);
+        let value = self.pc_next_8(bus);
+        self.write_8(bus, address, value);
         12
     }
 
-    fn load_8_from_immediate(&mut self, destination: Register8) -> usize {
-        let address = self.pc_next_16();
-        let value = self.mmu.borrow().read_8(address);
+    fn load_8_from_immediate(&mut self, bus: &mut impl Bus, destination: Register8) -> usize {
+        let address = self.pc_next_16(bus);
+        let value = self.read_8(bus, address);
         self.registers.set_8(destination, value);
         16
     }
 
-    fn load_8_at_immediate(&mut self, source: Register8) -> usize {
-        let address = self.pc_next_16();
+    fn load_8_at_immediate(&mut self, bus: &mut impl Bus, source: Register8) -> usize {
+        let address = self.pc_next_16(bus);
         let value = self.registers.get_8(source);
-        self.mmu.borrow_mut().write_8(address, value);
+        self.write_8(bus, address, value);
         16
     }
 
-    fn load_8_from_io(&mut self, destination: Register8, source: Register8) -> usize {
+    fn load_8_from_io(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register8,
+        source: Register8,
+    ) -> usize {
         let address = 0xFF00 + self.registers.get_8(source) as u16;
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         self.registers.set_8(destination, value);
         8
     }
 
-    fn load_8_from_io_immediate(&mut self, destination: Register8) -> usize {
-        let address = 0xFF00 + self.pc_next_8() as u16;
-        let value = self.mmu.borrow().read_8(address);
+    fn load_8_from_io_immediate(&mut self, bus: &mut impl Bus, destination: Register8) -> usize {
+        let address = 0xFF00 + self.pc_next_8(bus) as u16;
+        let value = self.read_8(bus, address);
         self.registers.set_8(destination, value);
         12
     }
 
-    fn load_8_at_io(&mut self, destination: Register8, source: Register8) -> usize {
+    fn load_8_at_io(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register8,
+        source: Register8,
+    ) -> usize {
         let address = 0xFF00 + self.registers.get_8(destination) as u16;
         let value = self.registers.get_8(source);
-        self.mmu.borrow_mut().write_8(address, value);
+        self.write_8(bus, address, value);
         8
     }
 
-    fn load_8_at_io_immediate(&mut self, source: Register8) -> usize {
-        let address = 0xFF00 + self.pc_next_8() as u16;
+    fn load_8_at_io_immediate(&mut self, bus: &mut impl Bus, source: Register8) -> usize {
+        let address = 0xFF00 + self.pc_next_8(bus) as u16;
         let value = self.registers.get_8(source);
-        self.mmu.borrow_mut().write_8(address, value);
+        self.write_8(bus, address, value);
         12
     }
 
@@ -974,27 +1128,32 @@ impl LR35902 {
         8
     }
 
-    fn load_16_at_immediate(&mut self, source: Register16) -> usize {
-        let address = self.pc_next_16();
+    fn load_16_at_immediate(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
+        let address = self.pc_next_16(bus);
         let value = self.registers.get_16(source);
-        self.mmu.borrow_mut().write_16(address, value);
+        self.write_16(bus, address, value);
         20
     }
 
-    fn load_16_immediate(&mut self, destination: Register16) -> usize {
-        let value = self.pc_next_16();
+    fn load_16_immediate(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
+        let value = self.pc_next_16(bus);
         self.registers.set_16(destination, value);
         12
     }
 
     // TODO: maybe bugged h_flag
-    fn load_16_add_immediate(&mut self, destination: Register16, source: Register16) -> usize {
-        let immediate = self.pc_next_8();
+    fn load_16_add_immediate(
+        &mut self,
+        bus: &mut impl Bus,
+        destination: Register16,
+        source: Register16,
+    ) -> usize {
+        let immediate = self.pc_next_8(bus);
         let value = self.registers.get_16(source);
 
         let res = (((value & 0x000F) as i8) + ((immediate & 0x0F) as i8)) as u8;
         let h_flag = res > 0x0F;
-        let c_flag = value.checked_add(immediate as u16) == None;
+        let c_flag = value.checked_add(immediate as u16).is_none();
 
         let value = value.wrapping_add_signed(immediate as i8 as i16);
         self.registers.set_16(destination, value);
@@ -1003,19 +1162,24 @@ impl LR35902 {
         12
     }
 
-    fn push(&mut self, source: Register16) -> usize {
-        let value = self.registers.get_16(source);
+    /// Pushes the high byte first, after an internal cycle.
+    fn push(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
+        let [low, high] = self.registers.get_16(source).to_le_bytes();
         let address = self.registers.get_16(Register16::SP);
-        self.registers.set_16(Register16::SP, address - 2);
-        self.mmu.borrow_mut().write_16(address - 2, value);
+        self.idle(bus);
+        self.write_8(bus, address.wrapping_sub(1), high);
+        self.write_8(bus, address.wrapping_sub(2), low);
+        self.registers
+            .set_16(Register16::SP, address.wrapping_sub(2));
         16
     }
 
-    fn pop(&mut self, destination: Register16) -> usize {
+    fn pop(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(Register16::SP);
-        let value = self.mmu.borrow().read_16(address);
+        let value = self.read_16(bus, address);
         self.registers.set_16(destination, value);
-        self.registers.set_16(Register16::SP, address + 2);
+        self.registers
+            .set_16(Register16::SP, address.wrapping_add(2));
         12
     }
 
@@ -1024,9 +1188,9 @@ impl LR35902 {
     // Helper functions for ADD and ADC to avoid code duplication
     fn _add_8_inner(&mut self, destination: u8, source: u8, carry: u8) -> u8 {
         let h_flag = (destination & 0x0F) + (source & 0x0F) + carry > 0x0F;
-        let mut c_flag = destination.checked_add(source) == None;
+        let mut c_flag = destination.checked_add(source).is_none();
         let mut res = destination.wrapping_add(source);
-        if let None = res.checked_add(carry) {
+        if res.checked_add(carry).is_none() {
             c_flag = true;
         }
         res = res.wrapping_add(carry);
@@ -1044,9 +1208,9 @@ impl LR35902 {
         4
     }
 
-    fn add_8_from(&mut self, source: Register16) -> usize {
+    fn add_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
 
         let res = self._add_8_inner(a_value, value, 0);
@@ -1054,8 +1218,8 @@ impl LR35902 {
         8
     }
 
-    fn add_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn add_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
 
         let res = self._add_8_inner(a_value, value, 0);
@@ -1077,9 +1241,9 @@ impl LR35902 {
         4
     }
 
-    fn add_carry_8_from(&mut self, source: Register16) -> usize {
+    fn add_carry_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
         let carry = if self.registers.get_carry_flag() {
             1u8
@@ -1092,8 +1256,8 @@ impl LR35902 {
         8
     }
 
-    fn add_carry_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn add_carry_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
         let carry = if self.registers.get_carry_flag() {
             1u8
@@ -1120,9 +1284,9 @@ impl LR35902 {
         4
     }
 
-    fn inc_8_at(&mut self, destination: Register16) -> usize {
+    fn inc_8_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
 
         let h_flag = (value & 0x0F) + 1 > 0x0F;
         let res = value.wrapping_add(1);
@@ -1131,7 +1295,7 @@ impl LR35902 {
         self.registers.set_zero_flag(z_flag);
         self.registers.set_n_flag(false);
         self.registers.set_h_flag(h_flag);
-        self.mmu.borrow_mut().write_8(address, res);
+        self.write_8(bus, address, res);
         12
     }
 
@@ -1141,7 +1305,7 @@ impl LR35902 {
 
         let h_flag = (d_value & 0x000F).wrapping_add(s_value.wrapping_add(0x000F)) > 0x0F;
         let mut c_flag = false;
-        if let None = d_value.checked_add(s_value) {
+        if d_value.checked_add(s_value).is_none() {
             c_flag = true;
         }
         let res = d_value.wrapping_add(s_value);
@@ -1154,13 +1318,13 @@ impl LR35902 {
     }
 
     // TODO: Maybe bugged h_flag
-    fn add_16_immediate(&mut self, destination: Register16) -> usize {
+    fn add_16_immediate(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let d_value = self.registers.get_16(destination);
-        let value = self.pc_next_8() as i8;
+        let value = self.pc_next_8(bus) as i8;
 
         let h_flag = (d_value & 0x000F).wrapping_add_signed((value & 0x0F) as i16) > 0x0F;
         let mut c_flag = false;
-        if let None = d_value.checked_add_signed(value as i16) {
+        if d_value.checked_add_signed(value as i16).is_none() {
             c_flag = true;
         }
         let res = d_value.wrapping_add_signed(value as i16);
@@ -1180,15 +1344,15 @@ impl LR35902 {
 
     // SUB & SBC helper function to avoid code duplication
     fn _sub_8_inner(&mut self, destination: u8, source: u8, carry: u8) -> u8 {
-        let mut h_flag = (destination & 0x0F).checked_sub(source & 0x0F) == None;
+        let mut h_flag = (destination & 0x0F).checked_sub(source & 0x0F).is_none();
         let h_res = (destination & 0x0F).wrapping_sub(source & 0x0F);
-        if let None = h_res.checked_sub(carry) {
+        if h_res.checked_sub(carry).is_none() {
             h_flag = true;
         }
 
-        let mut c_flag = destination.checked_sub(source) == None;
+        let mut c_flag = destination.checked_sub(source).is_none();
         let mut res = destination.wrapping_sub(source);
-        if let None = res.checked_sub(carry) {
+        if res.checked_sub(carry).is_none() {
             c_flag = true;
         }
         res = res.wrapping_sub(carry);
@@ -1207,9 +1371,9 @@ impl LR35902 {
         4
     }
 
-    fn sub_8_from(&mut self, source: Register16) -> usize {
+    fn sub_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
 
         let res = self._sub_8_inner(a_value, value, 0);
@@ -1217,8 +1381,8 @@ impl LR35902 {
         8
     }
 
-    fn sub_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn sub_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
 
         let res = self._sub_8_inner(a_value, value, 0);
@@ -1240,9 +1404,9 @@ impl LR35902 {
         4
     }
 
-    fn sub_carry_8_from(&mut self, source: Register16) -> usize {
+    fn sub_carry_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
         let carry = if self.registers.get_carry_flag() {
             1u8
@@ -1255,8 +1419,8 @@ impl LR35902 {
         8
     }
 
-    fn sub_carry_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn sub_carry_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
         let carry = if self.registers.get_carry_flag() {
             1u8
@@ -1272,7 +1436,7 @@ impl LR35902 {
     fn dec_8(&mut self, destination: Register8) -> usize {
         let value = self.registers.get_8(destination);
 
-        let h_flag = (value & 0x0F).checked_sub(1) == None;
+        let h_flag = (value & 0x0F).checked_sub(1).is_none();
         let res = value.wrapping_sub(1);
         let z_flag = res == 0;
 
@@ -1283,18 +1447,18 @@ impl LR35902 {
         4
     }
 
-    fn dec_8_at(&mut self, destination: Register16) -> usize {
+    fn dec_8_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
 
-        let h_flag = (value & 0x0F).checked_sub(1) == None;
+        let h_flag = (value & 0x0F).checked_sub(1).is_none();
         let res = value.wrapping_sub(1);
         let z_flag = res == 0;
 
         self.registers.set_zero_flag(z_flag);
         self.registers.set_n_flag(false);
         self.registers.set_h_flag(h_flag);
-        self.mmu.borrow_mut().write_8(address, res);
+        self.write_8(bus, address, res);
         8
     }
 
@@ -1322,17 +1486,17 @@ impl LR35902 {
         4
     }
 
-    fn and_8_from(&mut self, source: Register16) -> usize {
+    fn and_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
 
         self._and_8_inner(a_value, value);
         8
     }
 
-    fn and_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn and_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
 
         self._and_8_inner(a_value, value);
@@ -1355,17 +1519,17 @@ impl LR35902 {
         4
     }
 
-    fn xor_8_from(&mut self, source: Register16) -> usize {
+    fn xor_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
 
         self._xor_8_inner(a_value, value);
         8
     }
 
-    fn xor_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn xor_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
 
         self._xor_8_inner(a_value, value);
@@ -1388,17 +1552,17 @@ impl LR35902 {
         4
     }
 
-    fn or_8_from(&mut self, source: Register16) -> usize {
+    fn or_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
 
         self._or_8_inner(a_value, value);
         8
     }
 
-    fn or_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn or_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
 
         self._or_8_inner(a_value, value);
@@ -1413,17 +1577,17 @@ impl LR35902 {
         4
     }
 
-    fn cp_8_from(&mut self, source: Register16) -> usize {
+    fn cp_8_from(&mut self, bus: &mut impl Bus, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let a_value = self.registers.get_8(Register8::A);
 
         let _res = self._sub_8_inner(a_value, value, 0);
         8
     }
 
-    fn cp_8_immediate(&mut self) -> usize {
-        let value = self.pc_next_8();
+    fn cp_8_immediate(&mut self, bus: &mut impl Bus) -> usize {
+        let value = self.pc_next_8(bus);
         let a_value = self.registers.get_8(Register8::A);
 
         let _res = self._sub_8_inner(a_value, value, 0);
@@ -1494,8 +1658,8 @@ impl LR35902 {
         4
     }
 
-    fn jump_if_immediate_16(&mut self, condition: bool) -> usize {
-        let value = self.pc_next_16();
+    fn jump_if_immediate_16(&mut self, bus: &mut impl Bus, condition: bool) -> usize {
+        let value = self.pc_next_16(bus);
         if !condition {
             return 12;
         }
@@ -1504,8 +1668,8 @@ impl LR35902 {
         16
     }
 
-    fn jump_if_immediate_8(&mut self, condition: bool) -> usize {
-        let value = self.pc_next_8() as i8 as i16;
+    fn jump_if_immediate_8(&mut self, bus: &mut impl Bus, condition: bool) -> usize {
+        let value = self.pc_next_8(bus) as i8 as i16;
         if !condition {
             return 8;
         }
@@ -1516,63 +1680,99 @@ impl LR35902 {
         12
     }
 
-    fn call(&mut self, condition: bool) -> usize {
-        let address = self.pc_next_16();
+    fn call(&mut self, bus: &mut impl Bus, condition: bool) -> usize {
+        let address = self.pc_next_16(bus);
         if !condition {
             return 12;
         }
 
-        self.push(Register16::PC);
+        self.push(bus, Register16::PC);
         self.registers.set_16(Register16::PC, address);
         24
     }
 
-    fn call_vec(&mut self, address: u16) -> usize {
-        self.push(Register16::PC);
+    fn call_vec(&mut self, bus: &mut impl Bus, address: u16) -> usize {
+        self.push(bus, Register16::PC);
         self.registers.set_16(Register16::PC, address);
         16
     }
 
-    fn ret(&mut self) -> usize {
-        self.pop(Register16::PC);
+    fn ret(&mut self, bus: &mut impl Bus) -> usize {
+        self.pop(bus, Register16::PC);
         16
     }
 
-    fn ret_if(&mut self, condition: bool) -> usize {
+    fn ret_if(&mut self, bus: &mut impl Bus, condition: bool) -> usize {
+        // The condition is checked during an internal cycle
+        self.idle(bus);
         if !condition {
             return 8;
         }
 
-        self.pop(Register16::PC);
+        self.pop(bus, Register16::PC);
         20
     }
 
-    fn ret_interrupt(&mut self) -> usize {
+    fn ret_interrupt(&mut self, bus: &mut impl Bus) -> usize {
         self.ime = true;
-        self.pop(Register16::PC);
+        self.ime_delay = 0;
+        self.pop(bus, Register16::PC);
         16
     }
 
     // Miscellaneous instructions
 
-    fn stop(&mut self) -> usize {
-        self.pc_next_8();
+    /// With a button held, STOP acts as HALT. Otherwise the system enters STOP mode until a
+    /// button is pressed. STOP skips the following byte unless an interrupt is pending.
+    fn stop(&mut self, bus: &mut impl Bus) -> usize {
+        let button_held = bus.peek_8(0xFF00) & 0x0F != 0x0F;
+        let pending = bus.interrupts().highest_pending().is_some();
+
+        if !pending {
+            self.pc_next_8(bus);
+        }
+        if button_held {
+            self.halted = !pending;
+        } else {
+            bus.stop();
+        }
+        4
+    }
+
+    fn lock_up(&mut self, opcode: u8) -> usize {
+        let fault = Fault::IllegalOpcode {
+            pc: self.registers.pc.wrapping_sub(1),
+            opcode,
+        };
+        error!("{}", fault);
+        self.locked_up = true;
+        self.fault = Some(fault);
         4
     }
 
     fn disable_interrupts(&mut self) -> usize {
         self.ime = false;
+        self.ime_delay = 0;
         4
     }
 
-    // TODO: flag is supposed to be set *after* the next instruction
     fn enable_interrupts(&mut self) -> usize {
-        self.ime = true;
+        if !self.ime && self.ime_delay == 0 {
+            self.ime_delay = 2;
+        }
         4
     }
 
-    fn halt(&mut self) -> usize {
-        self.halted = true;
+    fn halt(&mut self, bus: &mut impl Bus) -> usize {
+        let pending = bus.interrupts().highest_pending().is_some();
+        if pending && self.ime_delay > 0 {
+            // Right after EI, the interrupt gets serviced and returns to the HALT
+            self.registers.pc = self.registers.pc.wrapping_sub(1);
+        } else if !self.ime && pending {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
         4
     }
 
@@ -1586,7 +1786,7 @@ impl LR35902 {
 
         if with_carry {
             let bit: u8 = if carry { 1u8 } else { 0u8 };
-            value = value & !(1u8 << 0) | (bit << 0);
+            value = value & !(1u8 << 0) | bit;
         }
         self.registers.set_8(Register8::A, value);
         self.registers.set_flags(false, false, false, r_carry);
@@ -1617,7 +1817,7 @@ impl LR35902 {
 
         if with_carry {
             let bit: u8 = if carry { 1u8 } else { 0u8 };
-            value = value & !(1u8 << 0) | (bit << 0);
+            value = value & !(1u8 << 0) | bit;
         }
         self.registers.set_flags(value == 0, false, false, r_carry);
         value
@@ -1631,11 +1831,11 @@ impl LR35902 {
         8
     }
 
-    fn rotate_left_at(&mut self, destination: Register16) -> usize {
+    fn rotate_left_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let result = self._rotate_left_inner(value, false);
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
 
         16
     }
@@ -1648,11 +1848,11 @@ impl LR35902 {
         8
     }
 
-    fn rotate_left_carry_at(&mut self, destination: Register16) -> usize {
+    fn rotate_left_carry_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let result = self._rotate_left_inner(value, true);
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
 
         16
     }
@@ -1678,11 +1878,11 @@ impl LR35902 {
         8
     }
 
-    fn rotate_right_at(&mut self, destination: Register16) -> usize {
+    fn rotate_right_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let result = self._rotate_right_inner(value, false);
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
 
         16
     }
@@ -1695,11 +1895,11 @@ impl LR35902 {
         8
     }
 
-    fn rotate_right_carry_at(&mut self, destination: Register16) -> usize {
+    fn rotate_right_carry_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let result = self._rotate_right_inner(value, true);
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
 
         16
     }
@@ -1715,13 +1915,13 @@ impl LR35902 {
         8
     }
 
-    fn shift_left_at(&mut self, destination: Register16) -> usize {
+    fn shift_left_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let carry = value & 0x80 != 0;
         let result = value << 1;
 
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
         self.registers.set_flags(result == 0, false, false, carry);
 
         16
@@ -1738,13 +1938,13 @@ impl LR35902 {
         8
     }
 
-    fn shift_right_at(&mut self, destination: Register16) -> usize {
+    fn shift_right_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let carry = value & 0x01 != 0;
         let result = (value >> 1) & !(1u8 << 7) | (value & 0x80);
 
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
         self.registers.set_flags(result == 0, false, false, carry);
 
         16
@@ -1752,7 +1952,7 @@ impl LR35902 {
 
     fn swap(&mut self, destination: Register8) -> usize {
         let value = self.registers.get_8(destination);
-        let result: u8 = (value << 4) | (value >> 4);
+        let result: u8 = value.rotate_right(4);
 
         self.registers.set_8(destination, result);
         self.registers.set_flags(result == 0, false, false, false);
@@ -1760,12 +1960,12 @@ impl LR35902 {
         8
     }
 
-    fn swap_at(&mut self, destination: Register16) -> usize {
+    fn swap_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
-        let result: u8 = (value << 4) | (value >> 4);
+        let value = self.read_8(bus, address);
+        let result: u8 = value.rotate_right(4);
 
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
         self.registers.set_flags(result == 0, false, false, false);
 
         16
@@ -1782,13 +1982,13 @@ impl LR35902 {
         8
     }
 
-    fn shift_right_logic_at(&mut self, destination: Register16) -> usize {
+    fn shift_right_logic_at(&mut self, bus: &mut impl Bus, destination: Register16) -> usize {
         let address = self.registers.get_16(destination);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let carry = value & 0x01 != 0;
         let result = value >> 1;
 
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
         self.registers.set_flags(result == 0, false, false, carry);
 
         16
@@ -1805,9 +2005,9 @@ impl LR35902 {
         8
     }
 
-    fn bit_at(&mut self, n: u8, source: Register16) -> usize {
+    fn bit_at(&mut self, bus: &mut impl Bus, n: u8, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let result = (value >> n) & 0x01 != 0;
 
         self.registers.set_zero_flag(result);
@@ -1825,12 +2025,12 @@ impl LR35902 {
         8
     }
 
-    fn reset_bit_at(&mut self, n: u8, source: Register16) -> usize {
+    fn reset_bit_at(&mut self, bus: &mut impl Bus, n: u8, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let result = value & !(1 << n);
 
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
         16
     }
 
@@ -1842,12 +2042,59 @@ impl LR35902 {
         8
     }
 
-    fn set_bit_at(&mut self, n: u8, source: Register16) -> usize {
+    fn set_bit_at(&mut self, bus: &mut impl Bus, n: u8, source: Register16) -> usize {
         let address = self.registers.get_16(source);
-        let value = self.mmu.borrow().read_8(address);
+        let value = self.read_8(bus, address);
         let result = value | (1 << n);
 
-        self.mmu.borrow_mut().write_8(address, result);
+        self.write_8(bus, address, result);
         16
     }
 }
+
+/// The tracer and faults not yet reported are not part of the state.
+impl Snapshot for LR35902 {
+    fn save(&self, state: &mut Writer) {
+        let registers = &self.registers;
+        for register in [
+            registers.af,
+            registers.bc,
+            registers.de,
+            registers.hl,
+            registers.sp,
+            registers.pc,
+        ] {
+            state.u16(register);
+        }
+        state.bool(self.ime);
+        state.u8(self.ime_delay);
+        state.bool(self.halted);
+        state.bool(self.halt_bug);
+        state.bool(self.locked_up);
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        let registers = &mut self.registers;
+        for register in [
+            &mut registers.af,
+            &mut registers.bc,
+            &mut registers.de,
+            &mut registers.hl,
+            &mut registers.sp,
+            &mut registers.pc,
+        ] {
+            *register = state.u16()?;
+        }
+        // The lower nibble of F always reads as 0
+        registers.af &= 0xFFF0;
+        self.ime = state.bool()?;
+        self.ime_delay = state.u8()?;
+        self.halted = state.bool()?;
+        self.halt_bug = state.bool()?;
+        self.locked_up = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
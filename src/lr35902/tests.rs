@@ -0,0 +1,303 @@
+//! Runs the SingleStepTests sm83 vectors against the CPU.
+//!
+//! The vectors are not vendored. Point `SM83_TESTS_DIR` at a directory holding the opcode files
+//! (`00.json` to `ff.json` and `cb 00.json` to `cb ff.json`) and run the ignored test:
+//!
+//! ```text
+//! SM83_TESTS_DIR=../sm83/v1 cargo test sm83 -- --ignored --nocapture
+//! ```
+
+use std::{env, fs, path::Path};
+
+use serde_json::Value;
+
+use super::{Register16, Register8, LR35902};
+use crate::{bus::Bus, interrupt::InterruptController};
+
+const TESTS_DIR_VAR: &str = "SM83_TESTS_DIR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cycle {
+    Read(u16, u8),
+    Write(u16, u8),
+    Idle,
+}
+
+/// Flat 64 KiB of RAM that records every bus cycle.
+struct TestBus {
+    memory: Box<[u8; 0x10000]>,
+    interrupts: InterruptController,
+    cycles: Vec<Cycle>,
+}
+
+impl TestBus {
+    fn new() -> Self {
+        TestBus {
+            memory: Box::new([0; 0x10000]),
+            interrupts: InterruptController::new(),
+            cycles: Vec::new(),
+        }
+    }
+}
+
+impl Bus for TestBus {
+    fn read_8(&mut self, address: u16) -> u8 {
+        let value = self.memory[address as usize];
+        self.cycles.push(Cycle::Read(address, value));
+        value
+    }
+
+    fn write_8(&mut self, address: u16, value: u8) {
+        if address == 0xFFFF {
+            self.interrupts.write_ie(value);
+        }
+        self.memory[address as usize] = value;
+        self.cycles.push(Cycle::Write(address, value));
+    }
+
+    fn idle(&mut self) {
+        self.cycles.push(Cycle::Idle);
+    }
+
+    fn peek_8(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn interrupts(&mut self) -> &mut InterruptController {
+        &mut self.interrupts
+    }
+
+    fn stop(&mut self) {}
+
+    fn is_stopped(&self) -> bool {
+        false
+    }
+}
+
+const REGISTERS: [(&str, Register8); 8] = [
+    ("a", Register8::A),
+    ("f", Register8::F),
+    ("b", Register8::B),
+    ("c", Register8::C),
+    ("d", Register8::D),
+    ("e", Register8::E),
+    ("h", Register8::H),
+    ("l", Register8::L),
+];
+
+fn number(state: &Value, key: &str) -> u64 {
+    state[key].as_u64().unwrap_or(0)
+}
+
+fn ram(state: &Value) -> impl Iterator<Item = (u16, u8)> + '_ {
+    state["ram"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some((entry[0].as_u64()? as u16, entry[1].as_u64()? as u8)))
+}
+
+fn expected_cycles(case: &Value) -> Vec<Cycle> {
+    case["cycles"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|cycle| {
+            let address = cycle[0].as_u64().unwrap_or(0) as u16;
+            let value = cycle[1].as_u64().unwrap_or(0) as u8;
+            match cycle[2].as_str() {
+                Some(kind) if kind.contains('r') => Cycle::Read(address, value),
+                Some(kind) if kind.contains('w') => Cycle::Write(address, value),
+                _ => Cycle::Idle,
+            }
+        })
+        .collect()
+}
+
+/// Runs a single test case and describes the first difference found, if any.
+fn run_case(case: &Value) -> Result<(), String> {
+    let initial = &case["initial"];
+    let expected = &case["final"];
+
+    let mut bus = TestBus::new();
+    for (address, value) in ram(initial) {
+        bus.memory[address as usize] = value;
+    }
+    bus.interrupts.write_ie(number(initial, "ie") as u8);
+
+    let mut cpu = LR35902::new();
+    for (name, register) in REGISTERS {
+        cpu.registers.set_8(register, number(initial, name) as u8);
+    }
+    cpu.registers
+        .set_16(Register16::SP, number(initial, "sp") as u16);
+    cpu.registers
+        .set_16(Register16::PC, number(initial, "pc") as u16);
+    cpu.ime = number(initial, "ime") != 0;
+    if number(initial, "ei") != 0 {
+        cpu.ime_delay = 1;
+    }
+
+    cpu.step(&mut bus);
+
+    let mut actual = vec![
+        ("pc", cpu.registers.get_16(Register16::PC) as u64),
+        ("sp", cpu.registers.get_16(Register16::SP) as u64),
+    ];
+    for (name, register) in REGISTERS {
+        actual.push((name, cpu.registers.get_8(register) as u64));
+    }
+    for (name, value) in actual {
+        if value != number(expected, name) {
+            return Err(format!(
+                "{}: expected {:#X}, got {:#X}",
+                name,
+                number(expected, name),
+                value
+            ));
+        }
+    }
+
+    // A pending EI counts as enabled, the vectors do not tell the two apart
+    let ime = cpu.ime || cpu.ime_delay > 0;
+    if ime != (number(expected, "ime") != 0) {
+        return Err(format!(
+            "ime: expected {}, got {}",
+            number(expected, "ime"),
+            ime
+        ));
+    }
+
+    for (address, value) in ram(expected) {
+        let actual = bus.memory[address as usize];
+        if actual != value {
+            return Err(format!(
+                "ram[{:#06X}]: expected {:#04X}, got {:#04X}",
+                address, value, actual
+            ));
+        }
+    }
+
+    let cycles = expected_cycles(case);
+    if bus.cycles != cycles {
+        return Err(format!(
+            "cycles: expected {:?}, got {:?}",
+            cycles, bus.cycles
+        ));
+    }
+    Ok(())
+}
+
+struct OpcodeResult {
+    name: String,
+    passed: usize,
+    total: usize,
+    first_failure: Option<String>,
+}
+
+fn run_file(path: &Path) -> OpcodeResult {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_uppercase())
+        .unwrap_or_default();
+    let contents = fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("Cannot read {}: {}", path.display(), err));
+    let cases: Vec<Value> = serde_json::from_str(&contents)
+        .unwrap_or_else(|err| panic!("Cannot parse {}: {}", path.display(), err));
+
+    let mut result = OpcodeResult {
+        name,
+        passed: 0,
+        total: cases.len(),
+        first_failure: None,
+    };
+    for case in &cases {
+        match run_case(case) {
+            Ok(()) => result.passed += 1,
+            Err(err) => {
+                if result.first_failure.is_none() {
+                    let case_name = case["name"].as_str().unwrap_or("?");
+                    result.first_failure = Some(format!("{}: {}", case_name, err));
+                }
+            }
+        }
+    }
+    result
+}
+
+#[test]
+#[ignore = "needs the sm83 vectors, see SM83_TESTS_DIR"]
+fn sm83() {
+    let Some(dir) = env::var_os(TESTS_DIR_VAR) else {
+        panic!("{} is not set, point it at the sm83 vectors", TESTS_DIR_VAR);
+    };
+
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("Cannot read {}: {}", Path::new(&dir).display(), err))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+
+    let results: Vec<_> = paths.iter().map(|path| run_file(path)).collect();
+    for result in &results {
+        let status = if result.passed == result.total {
+            "ok"
+        } else {
+            "FAIL"
+        };
+        println!(
+            "{:<6} {:>5}/{:<5} {}",
+            result.name, result.passed, result.total, status
+        );
+        if let Some(failure) = &result.first_failure {
+            println!("       {}", failure);
+        }
+    }
+
+    let divergent: Vec<_> = results
+        .iter()
+        .filter(|result| result.passed != result.total)
+        .map(|result| result.name.as_str())
+        .collect();
+    assert!(
+        divergent.is_empty(),
+        "{} of {} opcodes diverge: {}",
+        divergent.len(),
+        results.len(),
+        divergent.join(", ")
+    );
+}
+
+/// Runs the instruction at 0x0100 with the given memory, SP and HL.
+fn step_at(memory: &[(u16, u8)], sp: u16, hl: u16) -> LR35902 {
+    let mut bus = TestBus::new();
+    for &(address, value) in memory {
+        bus.memory[address as usize] = value;
+    }
+    let mut cpu = LR35902::new();
+    cpu.registers.set_16(Register16::PC, 0x0100);
+    cpu.registers.set_16(Register16::SP, sp);
+    cpu.registers.set_16(Register16::HL, hl);
+    cpu.step(&mut bus);
+    cpu
+}
+
+#[test]
+fn addresses_wrap_around() {
+    // RET with SP at the top of memory pops 0xFFFE and 0xFFFF, then SP wraps to 0
+    let cpu = step_at(&[(0x0100, 0xC9), (0xFFFE, 0x34), (0xFFFF, 0x12)], 0xFFFE, 0);
+    assert_eq!(cpu.registers.get_16(Register16::PC), 0x1234);
+    assert_eq!(cpu.registers.get_16(Register16::SP), 0x0000);
+
+    // LD (HL+),A at 0xFFFF
+    let cpu = step_at(&[(0x0100, 0x22)], 0xDFFF, 0xFFFF);
+    assert_eq!(cpu.registers.get_16(Register16::HL), 0x0000);
+
+    // LD (HL-),A at 0x0000
+    let cpu = step_at(&[(0x0100, 0x32)], 0xDFFF, 0x0000);
+    assert_eq!(cpu.registers.get_16(Register16::HL), 0xFFFF);
+}
@@ -1,23 +1,20 @@
-mod cartridge;
-mod clock;
-mod disassembler;
 mod dmg;
-mod graphics;
 mod gui;
-mod joypad;
-mod lr35902;
-mod mmu;
-mod ppu;
+mod screenshot;
+mod test_rom;
 mod thread;
-mod timer;
-mod tracer;
+mod verify;
+mod watcher;
 
 extern crate getopts;
 
-use dmg::DotMatrixGame;
+use dmg::{DotMatrixGame, SAVE_STATE_SLOTS};
+use dmg_rs::{boot_rom::BootRom, clock::SyncSource, emulator::CYCLES_PER_SECOND, model::Model};
+use getopts::Options;
 use gui::Gui;
-use std::{env, error, sync::mpsc::channel};
-use thread::{DmgMessage, GuiMessage};
+use screenshot::InputScript;
+use std::{env, error, path::Path, sync::mpsc::channel};
+use thread::{DmgMessage, GuiMessage, MovieStart};
 use tracing::Level;
 use tracing_flame::FlameLayer;
 use tracing_subscriber::{
@@ -26,12 +23,182 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
 };
 
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} [options] ROM", program);
+    print!("{}", opts.usage(&brief));
+}
+
 fn main() -> Result<(), Box<dyn error::Error>> {
-    let (flame_layer, _guard) = FlameLayer::with_file("./tracing.folded").unwrap();
+    let args: Vec<String> = env::args().collect();
+    let (program, args) = match args.split_first() {
+        Some((program, args)) => (program.as_str(), args),
+        None => ("dmg-rs", &[][..]),
+    };
+    let mut opts = Options::new();
+    opts.optopt(
+        "m",
+        "model",
+        "hardware model to emulate: dmg0, dmg (default), mgb, sgb, sgb2 or cgb",
+        "MODEL",
+    );
+    opts.optopt(
+        "b",
+        "boot-rom",
+        "run the boot ROM at PATH before the game instead of skipping it",
+        "PATH",
+    );
+    opts.optflag(
+        "w",
+        "watch",
+        "reload the ROM and reset when it changes on disk",
+    );
+    opts.optopt(
+        "",
+        "watch-restore",
+        "with --watch, load save state SLOT after reloading the ROM",
+        "SLOT",
+    );
+    opts.optflag(
+        "",
+        "log-illegal-access",
+        "log CPU accesses to VRAM and OAM while the PPU has them locked",
+    );
+    opts.optopt(
+        "",
+        "sync",
+        "pace emulation on the system clock (default) or on the display refresh, which \
+         expects a 60 Hz display",
+        "clock|vsync",
+    );
+    opts.optopt(
+        "",
+        "rewind",
+        "seconds of gameplay kept to rewind through (default 10, 0 disables rewinding)",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "record",
+        "record the buttons pressed from power on to the movie at PATH",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "play",
+        "play the movie at PATH back, recording over it once switched to read-write",
+        "PATH",
+    );
+    opts.optflag(
+        "t",
+        "test",
+        "run ROM without the GUI as a Blargg or Mooneye test ROM and exit with its result, a \
+         directory runs every ROM in it",
+    );
+    opts.optopt(
+        "",
+        "test-timeout",
+        "emulated seconds before a test ROM is considered failed (default 120)",
+        "SECONDS",
+    );
+    opts.optopt(
+        "s",
+        "screenshot",
+        "run ROM without the GUI and compare its last frame with the PNG at REFERENCE",
+        "REFERENCE",
+    );
+    opts.optopt(
+        "",
+        "frames",
+        "frames to run before taking the screenshot (default 60)",
+        "COUNT",
+    );
+    opts.optopt(
+        "",
+        "input",
+        "replay the button presses in SCRIPT while taking the screenshot",
+        "SCRIPT",
+    );
+    opts.optopt(
+        "",
+        "diff",
+        "where to write the diff image when the screenshot does not match (default diff.png)",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "verify",
+        "play MOVIE back without the GUI and compare the state after every frame with the \
+         hashes written next to it while recording",
+        "MOVIE",
+    );
+    opts.optflag("h", "help", "print this help menu");
+    let matches = opts.parse(args)?;
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_usage(program, &opts);
+        return Ok(());
+    }
+    let rom_path = matches.free[0].clone();
+    let model = matches
+        .opt_str("m")
+        .map(|name| name.parse::<Model>())
+        .transpose()?
+        .unwrap_or_default();
+    let boot_rom = matches
+        .opt_str("b")
+        .map(|path| BootRom::from_file(&path, model))
+        .transpose()?;
+    let watch_rom = matches.opt_present("w");
+    let watch_restore = matches
+        .opt_str("watch-restore")
+        .map(|slot| slot.parse::<usize>())
+        .transpose()?;
+    if let Some(slot) = watch_restore.filter(|slot| !(1..=SAVE_STATE_SLOTS).contains(slot)) {
+        return Err(format!("Save state slot {} out of 1 to {}", slot, SAVE_STATE_SLOTS).into());
+    }
+    let log_illegal_access = matches.opt_present("log-illegal-access");
+    let sync = match matches.opt_str("sync").as_deref() {
+        None | Some("clock") => SyncSource::Clock,
+        Some("vsync") => SyncSource::VSync,
+        Some(other) => return Err(format!("Unknown sync source {}", other).into()),
+    };
+    let rewind_depth = matches
+        .opt_str("rewind")
+        .map(|seconds| seconds.parse::<usize>())
+        .transpose()?
+        .unwrap_or(10);
+    let record_movie = matches.opt_str("record");
+    let play_movie = matches.opt_str("play");
+    let test_mode = matches.opt_present("t");
+    let test_timeout = matches
+        .opt_str("test-timeout")
+        .map(|seconds| seconds.parse::<usize>())
+        .transpose()?
+        .unwrap_or(120);
+    let reference = matches.opt_str("s");
+    let frames = matches
+        .opt_str("frames")
+        .map(|count| count.parse::<usize>())
+        .transpose()?
+        .unwrap_or(60);
+    let script = matches
+        .opt_str("input")
+        .map(|path| InputScript::from_file(&path))
+        .transpose()?
+        .unwrap_or_default();
+    let diff_path = matches
+        .opt_str("diff")
+        .unwrap_or_else(|| "diff.png".to_owned());
+    let verify_movie = matches.opt_str("verify");
+    let log_level = match test_mode || reference.is_some() || verify_movie.is_some() {
+        true => Level::WARN,
+        false => Level::DEBUG,
+    };
+
+    let (flame_layer, _guard) = FlameLayer::with_file("./tracing.folded")?;
     tracing_subscriber::registry()
         .with(
             fmt::Layer::new()
-                .with_writer(std::io::stdout.with_max_level(Level::DEBUG))
+                .with_writer(std::io::stdout.with_max_level(log_level))
                 .with_file(false)
                 .with_line_number(false)
                 .with_thread_ids(false)
@@ -40,14 +207,48 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         .with(flame_layer)
         .try_init()?;
 
+    if test_mode {
+        let max_cycles = test_timeout * CYCLES_PER_SECOND;
+        let passed = test_rom::run_all(Path::new(&rom_path), model, boot_rom, max_cycles)?;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    if let Some(reference) = reference {
+        let matched = screenshot::compare(
+            &rom_path,
+            model,
+            boot_rom,
+            frames,
+            &script,
+            Path::new(&reference),
+            Path::new(&diff_path),
+        )?;
+        std::process::exit(if matched { 0 } else { 1 });
+    }
+
+    if let Some(movie_path) = verify_movie {
+        let matched = verify::verify(&rom_path, model, boot_rom, &movie_path)?;
+        std::process::exit(if matched { 0 } else { 1 });
+    }
+
     let (gui_tx, gui_rx) = channel::<GuiMessage>();
     let (dmg_tx, dmg_rx) = channel::<DmgMessage>();
     let tx_end = gui_tx.clone();
 
-    let args: Vec<String> = env::args().collect();
-
     let handle = std::thread::spawn(move || {
-        let mut dmg = DotMatrixGame::new_with_rom_path(&args[1], dmg_tx, gui_rx)?;
+        let mut dmg = DotMatrixGame::new_with_rom_path(&rom_path, model, boot_rom, dmg_tx, gui_rx)?;
+        dmg.set_watch_rom(watch_rom);
+        dmg.set_watch_restore(watch_restore);
+        dmg.set_log_illegal_access(log_illegal_access);
+        dmg.set_sync(sync);
+        dmg.set_rewind_depth(rewind_depth);
+        if let Some(path) = record_movie {
+            dmg.set_movie_path(&path);
+            dmg.record_movie(MovieStart::PowerOn);
+        } else if let Some(path) = play_movie {
+            dmg.set_movie_path(&path);
+            dmg.play_movie()?;
+        }
         dmg.start_game()
     });
 
@@ -1,117 +1,604 @@
-use std::{cell::RefCell, rc::Rc, sync::Arc};
+use std::sync::Arc;
 
-use crate::{cartridge::Cartridge, joypad::Joypad, lr35902::TIMERBIT, timer::Timer};
+use tracing::warn;
 
+use crate::{
+    boot_rom::BootRom,
+    bus::Bus,
+    cartridge::Cartridge,
+    dma::OamDma,
+    interrupt::{Interrupt, InterruptController},
+    joypad::{DmgButton, Joypad},
+    model::Model,
+    ppu::{Mode, PixelProcessingUnit},
+    save_state::{self, Section, Sections, Snapshot, Writer},
+    scheduler::{Event, Scheduler, Timestamp},
+    serial::Serial,
+    timer::Timer,
+};
+
+/// Buses the CPU shares with the OAM DMA controller.
+#[derive(Debug, PartialEq, Eq)]
+enum MemoryBus {
+    External,
+    Video,
+    Oam,
+    Internal,
+}
+
+fn memory_bus(address: u16) -> MemoryBus {
+    match address {
+        0x8000..=0x9FFF => MemoryBus::Video,
+        0xFE00..=0xFEFF => MemoryBus::Oam,
+        0xFF00..=0xFFFF => MemoryBus::Internal,
+        _ => MemoryBus::External,
+    }
+}
+
+/// Bits of an I/O register that always read back as 1, and bits the CPU is able to write.
+///
+/// Unmapped registers read as 0xFF and ignore writes.
+fn io_register_masks(address: u16) -> (u8, u8) {
+    match address {
+        0xFF00 => (0xC0, 0x30),          // P1
+        0xFF01 => (0x00, 0xFF),          // SB
+        0xFF02 => (0x7E, 0x81),          // SC
+        0xFF04 => (0x00, 0xFF),          // DIV
+        0xFF05 => (0x00, 0xFF),          // TIMA
+        0xFF06 => (0x00, 0xFF),          // TMA
+        0xFF07 => (0xF8, 0x07),          // TAC
+        0xFF0F => (0xE0, 0x1F),          // IF
+        0xFF10 => (0x80, 0x7F),          // NR10
+        0xFF11 => (0x3F, 0xFF),          // NR11
+        0xFF12 => (0x00, 0xFF),          // NR12
+        0xFF13 => (0xFF, 0xFF),          // NR13
+        0xFF14 => (0xBF, 0xC7),          // NR14
+        0xFF16 => (0x3F, 0xFF),          // NR21
+        0xFF17 => (0x00, 0xFF),          // NR22
+        0xFF18 => (0xFF, 0xFF),          // NR23
+        0xFF19 => (0xBF, 0xC7),          // NR24
+        0xFF1A => (0x7F, 0x80),          // NR30
+        0xFF1B => (0xFF, 0xFF),          // NR31
+        0xFF1C => (0x9F, 0x60),          // NR32
+        0xFF1D => (0xFF, 0xFF),          // NR33
+        0xFF1E => (0xBF, 0xC7),          // NR34
+        0xFF20 => (0xFF, 0x3F),          // NR41
+        0xFF21 => (0x00, 0xFF),          // NR42
+        0xFF22 => (0x00, 0xFF),          // NR43
+        0xFF23 => (0xBF, 0xC0),          // NR44
+        0xFF24 => (0x00, 0xFF),          // NR50
+        0xFF25 => (0x00, 0xFF),          // NR51
+        0xFF26 => (0x70, 0x80),          // NR52
+        0xFF30..=0xFF3F => (0x00, 0xFF), // Wave RAM
+        0xFF40 => (0x00, 0xFF),          // LCDC
+        0xFF41 => (0x80, 0x78),          // STAT
+        0xFF42 => (0x00, 0xFF),          // SCY
+        0xFF43 => (0x00, 0xFF),          // SCX
+        0xFF44 => (0x00, 0x00),          // LY
+        0xFF45 => (0x00, 0xFF),          // LYC
+        0xFF46 => (0x00, 0xFF),          // DMA
+        0xFF47 => (0x00, 0xFF),          // BGP
+        0xFF48 => (0x00, 0xFF),          // OBP0
+        0xFF49 => (0x00, 0xFF),          // OBP1
+        0xFF4A => (0x00, 0xFF),          // WY
+        0xFF4B => (0x00, 0xFF),          // WX
+        0xFF50 => (0xFF, 0x01),          // BANK
+        _ => (0xFF, 0x00),
+    }
+}
+
+/// I/O registers as the DMG boot ROM leaves them when jumping to 0x0100.
+const DMG_POST_BOOT_IO: &[(u16, u8)] = &[
+    (0xFF01, 0x00), // SB
+    (0xFF02, 0x7E), // SC
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF26, 0xF1), // NR52
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF45, 0x00), // LYC
+    (0xFF46, 0xFF), // DMA
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+    (0xFF50, 0x01), // BANK
+];
+
+/// Registers the other models leave with a different value than the DMG.
+fn post_boot_io_overrides(model: Model) -> &'static [(u16, u8)] {
+    match model {
+        Model::Dmg0 | Model::Dmg | Model::Mgb => &[],
+        Model::Sgb | Model::Sgb2 => &[
+            (0xFF26, 0xF0), // NR52
+        ],
+        Model::CgbDmg => &[
+            (0xFF02, 0x7F), // SC
+            (0xFF46, 0x00), // DMA
+        ],
+    }
+}
+
+/// Internal DIV counter when the boot ROM hands over to the cartridge.
+///
+/// The SGB and CGB boot ROMs take a variable amount of time, their values are approximations.
+fn post_boot_div(model: Model) -> u16 {
+    match model {
+        Model::Dmg0 => 0x1830,
+        Model::Dmg | Model::Mgb => 0xABCC,
+        Model::Sgb | Model::Sgb2 => 0xD85C,
+        Model::CgbDmg => 0x267C,
+    }
+}
+
+/// Tile the boot ROM draws after the logo.
+const REGISTERED_MARK_TILE: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+/// Scales a 4 bit logo row to 8 pixels by doubling every bit.
+fn double_logo_bits(nibble: u8) -> u8 {
+    (0..4).fold(0, |acc, bit| match nibble & (1 << bit) {
+        0 => acc,
+        _ => acc | (0b11 << (bit * 2)),
+    })
+}
+
+/// System bus owning every component the CPU talks to.
 #[derive(Debug)]
 pub struct MemoryMapUnit {
-    memory: [u8; 0x10000],
+    wram: [u8; 0x2000],
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    interrupts: InterruptController,
     cartridge: Box<dyn Cartridge>,
-    boot_rom: &'static [u8; 256],
-    joypad: Rc<RefCell<Joypad>>,
+    boot_rom: Option<BootRom>,
+    model: Model,
+    joypad: Joypad,
+    serial: Serial,
     timer: Timer,
+    dma: OamDma,
+    ppu: PixelProcessingUnit,
+    scheduler: Scheduler,
+    /// Set by STOP, the system clock is halted until a selected joypad line goes low.
+    stopped: bool,
+    log_illegal_access: bool,
 }
 
 impl MemoryMapUnit {
-    pub fn new(cartridge: Box<dyn Cartridge>, joypad: Rc<RefCell<Joypad>>) -> Self {
-        MemoryMapUnit {
-            memory: [0u8; 0x10000],
+    /// Without a boot ROM, the machine starts in the state the boot ROM leaves it in.
+    pub fn new(cartridge: Box<dyn Cartridge>, model: Model, boot_rom: Option<BootRom>) -> Self {
+        let mut mmu = MemoryMapUnit {
+            wram: [0u8; 0x2000],
+            io: [0u8; 0x80],
+            hram: [0u8; 0x7F],
+            interrupts: InterruptController::new(),
             cartridge,
-            boot_rom: include_bytes!("../dmg_boot.bin"),
-            joypad,
+            boot_rom,
+            model,
+            joypad: Joypad::new(),
+            serial: Serial::new(),
             timer: Timer::new(),
+            dma: OamDma::new(),
+            ppu: PixelProcessingUnit::new(model),
+            scheduler: Scheduler::new(),
+            stopped: false,
+            log_illegal_access: false,
+        };
+        mmu.ppu.start(&mut mmu.scheduler);
+        if mmu.boot_rom.is_none() {
+            mmu.skip_boot();
+        }
+        mmu
+    }
+
+    fn skip_boot(&mut self) {
+        self.timer = Timer::with_div(post_boot_div(self.model));
+        let registers = DMG_POST_BOOT_IO
+            .iter()
+            .chain(post_boot_io_overrides(self.model));
+        // IF goes last, as writing STAT with the LCD on requests an interrupt on monochrome
+        // models that the boot ROM does not leave pending
+        let (interrupt_flags, others): (Vec<_>, Vec<_>) =
+            registers.partition(|(address, _)| *address == 0xFF0F);
+        for &(address, value) in others.into_iter().chain(interrupt_flags) {
+            match address {
+                0xFF01 | 0xFF02 | 0xFF05..=0xFF07 | 0xFF0F | 0xFF40..=0xFF45 | 0xFF47..=0xFF4B => {
+                    self.write_io(address, value)
+                }
+                _ => self.io[(address - 0xFF00) as usize] = value,
+            }
+        }
+        if self.model.leaves_logo_in_vram() {
+            self.load_logo();
+        }
+    }
+
+    /// Draws the logo from the cartridge header in VRAM like the boot ROM does.
+    fn load_logo(&mut self) {
+        let mut tile_address = 0x8010;
+        for header_address in 0x0104..=0x0133 {
+            let value = self.cartridge.read_8(header_address);
+            for nibble in [value >> 4, value & 0x0F] {
+                let row = double_logo_bits(nibble);
+                self.ppu.write_vram(tile_address, row);
+                self.ppu.write_vram(tile_address + 2, row);
+                tile_address += 4;
+            }
+        }
+
+        for (i, &row) in REGISTERED_MARK_TILE.iter().enumerate() {
+            self.ppu.write_vram(0x8190 + i as u16 * 2, row);
+        }
+
+        self.ppu.write_vram(0x9910, 0x19);
+        for i in 0..12 {
+            self.ppu.write_vram(0x9904 + i, 0x01 + i as u8);
+            self.ppu.write_vram(0x9924 + i, 0x0D + i as u8);
+        }
+    }
+
+    /// Raises the joypad interrupt when the press pulls a selected P1 line low.
+    ///
+    /// This also brings the system out of STOP mode.
+    pub fn press_button(&mut self, button: DmgButton) {
+        if self.joypad.button_pressed(button) {
+            self.interrupts.request(Interrupt::Joypad);
+            self.stopped = false;
+        }
+    }
+
+    pub fn release_button(&mut self, button: DmgButton) {
+        self.joypad.button_released(button);
+    }
+
+    /// Bytes sent over the serial port since the last call.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial.take_output()
+    }
+
+    pub fn ppu(&self) -> &PixelProcessingUnit {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut PixelProcessingUnit {
+        &mut self.ppu
+    }
+
+    /// Logs every CPU access to VRAM or OAM while the PPU is using them.
+    pub fn set_log_illegal_access(&mut self, enabled: bool) {
+        self.log_illegal_access = enabled;
+    }
+
+    /// Whether the PPU currently keeps the CPU from accessing the address.
+    ///
+    /// VRAM is locked during pixel transfer and OAM during OAM search and pixel transfer.
+    fn locked_by_ppu(&self, address: u16) -> bool {
+        if !self.ppu.lcd_enabled() {
+            return false;
+        }
+
+        match memory_bus(address) {
+            MemoryBus::Video => matches!(self.ppu.mode(), Mode::PixelTransfer),
+            MemoryBus::Oam => matches!(self.ppu.mode(), Mode::OAMSearch | Mode::PixelTransfer),
+            _ => false,
         }
     }
 
-    fn boot_rom_enabled(&self) -> bool {
-        self.memory[0xFF50] == 0
+    fn report_illegal_access(&self, address: u16, value: Option<u8>) {
+        if self.log_illegal_access {
+            warn!(
+                address = format!("{:04X}", address),
+                ?value,
+                mode = ?self.ppu.mode(),
+                ly = self.ppu.read_register(0xFF44),
+                "CPU accessed memory locked by the PPU"
+            );
+        }
     }
 
-    pub fn read_8(&self, address: u16) -> u8 {
-        if self.boot_rom_enabled() && address <= 0xFF {
-            return self.boot_rom[address as usize];
+    fn boot_rom(&self) -> Option<&BootRom> {
+        match self.io[0x50] & 0x01 {
+            0 => self.boot_rom.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn read_unrestricted(&self, address: u16) -> u8 {
+        if let Some(value) = self
+            .boot_rom()
+            .and_then(|boot_rom| boot_rom.read_8(address))
+        {
+            return value;
         }
 
         match address {
             0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read_8(address),
-            0xFF04..=0xFF07 => self.timer.read_8(address),
-            0xFF00 => self.joypad.borrow().read(),
-            _ => self.memory[address as usize],
+            0x8000..=0x9FFF => self.ppu.read_vram(address),
+            0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize],
+            0xE000..=0xFDFF => self.wram[(address - 0xE000) as usize],
+            0xFE00..=0xFE9F => self.ppu.read_oam(address),
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.read_io(address),
+            0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize],
+            0xFFFF => self.interrupts.read_ie(),
         }
     }
 
-    pub fn read_16(&self, address: u16) -> u16 {
-        if self.boot_rom_enabled() && address <= 0xFF {
-            let n1 = self.read_8(address);
-            let n2 = self.read_8(address + 1);
-            return u16::from_le_bytes([n1, n2]);
-        }
+    fn read_io(&self, address: u16) -> u8 {
+        let (read_mask, _) = io_register_masks(address);
+        let value = match address {
+            0xFF00 => self.joypad.read(),
+            0xFF01 | 0xFF02 => self.serial.read_8(address),
+            0xFF04..=0xFF07 => self.timer.read_8(address, self.scheduler.now()),
+            0xFF0F => self.interrupts.read_if(),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read_register(address),
+            _ => self.io[(address - 0xFF00) as usize],
+        };
+        value | read_mask
+    }
 
+    fn write_io(&mut self, address: u16, value: u8) {
         match address {
-            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read_16(address),
+            0xFF00 => {
+                if self.joypad.write(value) {
+                    self.interrupts.request(Interrupt::Joypad);
+                }
+            }
+            0xFF01 | 0xFF02 => self.serial.write_8(address, value, &mut self.scheduler),
+            0xFF04..=0xFF07 => self.timer.write_8(address, value, &mut self.scheduler),
+            0xFF0F => self.interrupts.write_if(value),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => {
+                self.ppu
+                    .write_register(address, value, &mut self.interrupts)
+            }
+            0xFF46 => {
+                self.io[0x46] = value;
+                self.dma.start(value);
+            }
+            // The boot ROM can't be mapped back once disabled
+            0xFF50 => self.io[0x50] |= value & 0x01,
             _ => {
-                let n1 = self.read_8(address);
-                let n2 = self.read_8(address + 1);
-                u16::from_le_bytes([n1, n2])
+                let (_, write_mask) = io_register_masks(address);
+                let register = &mut self.io[(address - 0xFF00) as usize];
+                *register = (*register & !write_mask) | (value & write_mask);
             }
         }
     }
 
-    pub fn write_8(&mut self, address: u16, value: u8) {
-        match address {
-            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write_8(address, value),
-            0xFF00 => (*self.joypad).borrow_mut().write(value),
-            0xFF04..=0xFF07 => self.timer.write_8(address, value),
-            0xFF46 => self.dma_transfer(value),
-            _ => self.memory[address as usize] = value,
+    /// Advances the system by one M-cycle, running the OAM DMA controller and every event that
+    /// falls within the cycle.
+    ///
+    /// Nothing advances while the system is in STOP mode.
+    fn tick_m_cycle(&mut self) {
+        if self.stopped {
+            return;
+        }
+
+        self.scheduler.advance(4);
+        while let Some((event, time)) = self.scheduler.pop_due() {
+            self.handle_event(event, time);
         }
+        self.dma_tick();
     }
 
-    pub fn write_16(&mut self, address: u16, value: u16) {
-        match address {
-            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write_16(address, value),
-            _ => {
-                let bytes = value.to_le_bytes();
-                self.memory[address as usize] = bytes[0];
-                self.memory[(address + 1) as usize] = bytes[1];
+    fn handle_event(&mut self, event: Event, time: Timestamp) {
+        match event {
+            Event::PpuMode => self
+                .ppu
+                .change_mode(time, &mut self.interrupts, &mut self.scheduler),
+            Event::TimerOverflow => {
+                self.timer.overflow(time, &mut self.scheduler);
+                self.interrupts.request(Interrupt::Timer);
+            }
+            Event::SerialTransfer => {
+                self.serial.complete_transfer();
+                self.interrupts.request(Interrupt::Serial);
             }
         }
     }
 
-    pub fn timer_tick(&mut self) {
-        if self.timer.tick() {
-            let value = self.memory[0xFF0F];
-            let value = value | TIMERBIT;
-            self.memory[0xFF0F] = value;
+    fn dma_tick(&mut self) {
+        if let Some((source, index)) = self.dma.tick() {
+            let value = self.read_unrestricted(source);
+            self.dma.set_last_value(value);
+            self.ppu.write_oam(0xFE00 + index, value);
         }
     }
 
     pub fn get_memory_dump(&self) -> Arc<[u8; 0x10000]> {
-        let mut memory = self.memory.clone();
+        let mut memory = [0u8; 0x10000];
         let rom = self.cartridge.dump_rom();
 
-        memory.as_mut_slice()[0x0000..0x8000].copy_from_slice(&rom);
-        if self.boot_rom_enabled() {
-            memory.as_mut_slice()[0..256].copy_from_slice(self.boot_rom);
+        memory[0x0000..0x8000].copy_from_slice(&rom[0x0000..0x8000]);
+        if let Some(boot_rom) = self.boot_rom() {
+            for address in 0x0000..0x0900u16 {
+                if let Some(value) = boot_rom.read_8(address) {
+                    memory[address as usize] = value;
+                }
+            }
         }
+        memory[0x8000..0xA000].copy_from_slice(self.ppu.vram());
+        memory[0xC000..0xE000].copy_from_slice(&self.wram);
+        memory[0xE000..0xFE00].copy_from_slice(&self.wram[0x0000..0x1E00]);
+        memory[0xFE00..0xFEA0].copy_from_slice(self.ppu.oam());
+        for address in 0xFF00..=0xFF7Fu16 {
+            memory[address as usize] = self.read_io(address);
+        }
+        memory[0xFF80..0xFFFF].copy_from_slice(&self.hram);
+        memory[0xFFFF] = self.interrupts.read_ie();
         Arc::new(memory)
     }
 
-    pub fn vram(&self) -> Vec<u8> {
-        self.memory[0x8000..=0x9FFF].to_vec()
+    /// Work RAM, which keeps its content across a reset.
+    pub fn wram(&self) -> &[u8; 0x2000] {
+        &self.wram
     }
 
-    fn dma_transfer(&mut self, source: u8) {
-        let starting_address = (source as u16) << 8;
+    pub fn load_wram(&mut self, wram: &[u8; 0x2000]) {
+        self.wram = *wram;
+    }
 
-        for i in 0..0x100 {
-            let address = starting_address + i;
-            let destination = 0xFE00 + i;
-            let value = self.read_8(address);
-            self.write_8(destination, value);
+    /// Fills work RAM and high RAM with the garbage they power on with, generated from `seed`.
+    pub fn randomize_ram(&mut self, seed: u64) {
+        // SplitMix64, any seed gives a full period
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            (z ^ (z >> 31)) as u8
+        };
+        for byte in self.wram.iter_mut().chain(self.hram.iter_mut()) {
+            *byte = next();
         }
     }
 
-    pub fn borrow_rom(&self) -> &[u8] {
-        &self.cartridge.borrow_rom()
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.cartridge
+            .has_battery()
+            .then(|| self.cartridge.dump_ram())
+    }
+
+    /// Restores the RAM of a battery backed cartridge, other cartridges ignore it.
+    pub fn load_battery_ram(&mut self, ram: &[u8]) {
+        if self.cartridge.has_battery() {
+            self.cartridge.load_ram(ram);
+        }
+    }
+
+    /// Saves the bus and every component on it, the PPU, the timer and the cartridge each in
+    /// their own section.
+    pub fn save_state(&self, state: &mut Writer) {
+        state.section(Section::Mmu, |state| {
+            state.bytes(&self.wram);
+            state.bytes(&self.io);
+            state.bytes(&self.hram);
+            self.interrupts.save(state);
+            self.joypad.save(state);
+            self.serial.save(state);
+            self.dma.save(state);
+            self.scheduler.save(state);
+            state.bool(self.stopped);
+        });
+        state.section(Section::Ppu, |state| self.ppu.save(state));
+        state.section(Section::Timer, |state| self.timer.save(state));
+        state.section(Section::Cartridge, |state| self.cartridge.save(state));
+    }
+
+    pub fn load_state(&mut self, save_state: &Sections) -> save_state::Result<()> {
+        let mut state = save_state.section(Section::Mmu)?;
+        state.bytes(&mut self.wram)?;
+        state.bytes(&mut self.io)?;
+        state.bytes(&mut self.hram)?;
+        self.interrupts.load(&mut state)?;
+        self.joypad.load(&mut state)?;
+        self.serial.load(&mut state)?;
+        self.dma.load(&mut state)?;
+        self.scheduler.load(&mut state)?;
+        self.stopped = state.bool()?;
+
+        self.ppu.load(&mut save_state.section(Section::Ppu)?)?;
+        self.timer.load(&mut save_state.section(Section::Timer)?)?;
+        self.cartridge
+            .load(&mut save_state.section(Section::Cartridge)?)
     }
 }
+
+impl Bus for MemoryMapUnit {
+    /// Reads a byte as seen by the CPU.
+    ///
+    /// While an OAM DMA transfer is running, OAM reads return 0xFF and reads on the bus used by
+    /// the transfer return the byte being copied. Memory locked by the PPU reads as 0xFF.
+    fn read_8(&mut self, address: u16) -> u8 {
+        self.tick_m_cycle();
+        if let Some(source) = self.dma.source() {
+            match memory_bus(address) {
+                MemoryBus::Oam => return 0xFF,
+                bus if bus == memory_bus(source) => return self.dma.last_value(),
+                _ => (),
+            }
+        }
+        if self.locked_by_ppu(address) {
+            self.report_illegal_access(address, None);
+            return 0xFF;
+        }
+        self.read_unrestricted(address)
+    }
+
+    /// Writes a byte from the CPU. Writes to OAM or to the bus used by a running OAM DMA
+    /// transfer are dropped, as are writes to memory locked by the PPU.
+    fn write_8(&mut self, address: u16, value: u8) {
+        self.tick_m_cycle();
+        if let Some(source) = self.dma.source() {
+            let bus = memory_bus(address);
+            if bus == MemoryBus::Oam || bus == memory_bus(source) {
+                return;
+            }
+        }
+        if self.locked_by_ppu(address) {
+            self.report_illegal_access(address, Some(value));
+            return;
+        }
+
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write_8(address, value),
+            0x8000..=0x9FFF => self.ppu.write_vram(address, value),
+            0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize] = value,
+            0xE000..=0xFDFF => self.wram[(address - 0xE000) as usize] = value,
+            0xFE00..=0xFE9F => self.ppu.write_oam(address, value),
+            0xFEA0..=0xFEFF => (),
+            0xFF00..=0xFF7F => self.write_io(address, value),
+            0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize] = value,
+            0xFFFF => self.interrupts.write_ie(value),
+        }
+    }
+
+    fn idle(&mut self) {
+        self.tick_m_cycle();
+    }
+
+    fn peek_8(&self, address: u16) -> u8 {
+        self.read_unrestricted(address)
+    }
+
+    fn interrupts(&mut self) -> &mut InterruptController {
+        &mut self.interrupts
+    }
+
+    fn stop(&mut self) {
+        self.timer.write_8(0xFF04, 0, &mut self.scheduler);
+        self.stopped = true;
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,10 @@
+use super::MemoryMapUnit;
+use crate::{bus::Bus, cartridge, model::Model};
+
+#[test]
+fn skip_boot_leaves_only_vblank_pending() {
+    for model in [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Sgb2] {
+        let mmu = MemoryMapUnit::new(cartridge::empty(), model, None);
+        assert_eq!(mmu.peek_8(0xFF0F), 0xE1, "{}", model);
+    }
+}
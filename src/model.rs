@@ -0,0 +1,77 @@
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("Unknown model {0}, expected one of dmg0, dmg, mgb, sgb, sgb2, cgb.")]
+pub struct UnknownModel(String);
+
+/// Game Boy revision being emulated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// Early Japanese DMG with the DMG0 boot ROM.
+    Dmg0,
+    #[default]
+    Dmg,
+    /// Game Boy Pocket and Light.
+    Mgb,
+    Sgb,
+    Sgb2,
+    /// Game Boy Color running a monochrome cartridge.
+    CgbDmg,
+}
+
+impl Model {
+    pub fn is_cgb(self) -> bool {
+        self == Model::CgbDmg
+    }
+
+    /// Size of the boot ROM this model runs.
+    pub fn boot_rom_size(self) -> usize {
+        match self {
+            Model::CgbDmg => 0x900,
+            _ => 0x100,
+        }
+    }
+
+    /// Whether writing to STAT during HBlank, VBlank or while LY equals LYC raises a spurious
+    /// STAT interrupt, as every interrupt source gets enabled for one cycle.
+    pub fn has_stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    /// Whether the boot ROM leaves the logo tiles and tile map in VRAM.
+    pub fn leaves_logo_in_vram(self) -> bool {
+        matches!(self, Model::Dmg0 | Model::Dmg | Model::Mgb)
+    }
+}
+
+impl FromStr for Model {
+    type Err = UnknownModel;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Model::Dmg0),
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "sgb2" => Ok(Model::Sgb2),
+            "cgb" => Ok(Model::CgbDmg),
+            _ => Err(UnknownModel(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Sgb2 => "sgb2",
+            Model::CgbDmg => "cgb",
+        };
+        write!(f, "{}", name)
+    }
+}
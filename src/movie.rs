@@ -0,0 +1,319 @@
+//! Input movies, replaying a run exactly from the buttons held on every frame.
+//!
+//! A movie is a text file. A header gives what the run depends on, then every frame has a line
+//! with the buttons held during it, in the order Up, Down, Left, Right, Start, select, B, A and
+//! with a `.` for a released button. Empty lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! dmg-rs movie 1
+//! model dmg
+//! rom 7BA62566
+//! boot-rom no
+//! start power-on
+//! |........|
+//! |....S...|
+//! |...R...A|
+//! ```
+//!
+//! `rom` is the CRC-32 of the ROM. `start` is either `power-on` or `state` followed by a save
+//! state encoded in base64, which the movie starts from instead.
+
+use std::{fmt, fs, io, result, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{
+    joypad::DmgButton,
+    model::Model,
+    save_state::{self, SaveState},
+    Emulator,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Could not read movie: {0}.")]
+    Loading(#[from] io::Error),
+    #[error("Movie line {line}: {reason}.")]
+    Invalid { line: usize, reason: String },
+    #[error("Movie was recorded with another ROM (hash {found:08X}, expected {expected:08X}).")]
+    RomMismatch { expected: u32, found: u32 },
+    #[error("Movie was recorded on {found}, the emulated model is {expected}.")]
+    ModelMismatch { expected: Model, found: Model },
+    #[error("Movie was recorded with a different boot ROM setting.")]
+    BootRomMismatch,
+    #[error("Could not load the movie's start state: {0}")]
+    SaveState(#[from] save_state::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+const MAGIC: &str = "dmg-rs movie";
+const VERSION: u32 = 1;
+
+/// Buttons in the order of the frame lines, with their letter.
+const INPUT_LETTERS: [(DmgButton, char); 8] = [
+    (DmgButton::Up, 'U'),
+    (DmgButton::Down, 'D'),
+    (DmgButton::Left, 'L'),
+    (DmgButton::Right, 'R'),
+    (DmgButton::Start, 'S'),
+    (DmgButton::Select, 's'),
+    (DmgButton::B, 'B'),
+    (DmgButton::A, 'A'),
+];
+
+/// Buttons held during a frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Input(u8);
+
+impl Input {
+    fn bit(button: DmgButton) -> u8 {
+        let index = DmgButton::ALL
+            .iter()
+            .position(|&b| b == button)
+            .unwrap_or(0);
+        1 << index
+    }
+
+    pub fn is_pressed(self, button: DmgButton) -> bool {
+        self.0 & Self::bit(button) != 0
+    }
+
+    pub fn set(&mut self, button: DmgButton, pressed: bool) {
+        match pressed {
+            true => self.0 |= Self::bit(button),
+            false => self.0 &= !Self::bit(button),
+        }
+    }
+
+    pub fn buttons(self) -> Vec<DmgButton> {
+        DmgButton::ALL
+            .into_iter()
+            .filter(|&button| self.is_pressed(button))
+            .collect()
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letters: String = INPUT_LETTERS
+            .iter()
+            .map(|&(button, letter)| match self.is_pressed(button) {
+                true => letter,
+                false => '.',
+            })
+            .collect();
+        write!(f, "|{}|", letters)
+    }
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let letters = s
+            .strip_prefix('|')
+            .and_then(|s| s.strip_suffix('|'))
+            .filter(|letters| letters.chars().count() == INPUT_LETTERS.len());
+        let Some(letters) = letters else {
+            return Err(format!("expected |UDLRSsBA|, got {}", s));
+        };
+
+        let mut input = Input::default();
+        for (letter, &(button, expected)) in letters.chars().zip(&INPUT_LETTERS) {
+            match letter {
+                '.' => (),
+                _ if letter == expected => input.set(button, true),
+                _ => return Err(format!("expected {} or . for {:?}", expected, button)),
+            }
+        }
+        Ok(input)
+    }
+}
+
+/// State of the machine on the first frame of a movie.
+#[derive(Debug, Clone)]
+pub enum Start {
+    PowerOn,
+    SaveState(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub model: Model,
+    pub rom_hash: u32,
+    pub boot_rom: bool,
+    pub start: Start,
+    /// Buttons held on every frame, from the start.
+    pub frames: Vec<Input>,
+}
+
+impl Movie {
+    /// An empty movie for the game running in `emulator`.
+    pub fn new(emulator: &Emulator, start: Start) -> Self {
+        Self {
+            model: emulator.model(),
+            rom_hash: emulator.rom_hash(),
+            boot_rom: emulator.has_boot_rom(),
+            start,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Puts the machine in the state the movie starts from.
+    pub fn start(&self, emulator: &mut Emulator) -> Result<()> {
+        if self.rom_hash != emulator.rom_hash() {
+            return Err(Error::RomMismatch {
+                expected: emulator.rom_hash(),
+                found: self.rom_hash,
+            });
+        }
+        if self.model != emulator.model() {
+            return Err(Error::ModelMismatch {
+                expected: emulator.model(),
+                found: self.model,
+            });
+        }
+        if self.boot_rom != emulator.has_boot_rom() {
+            return Err(Error::BootRomMismatch);
+        }
+
+        match &self.start {
+            Start::PowerOn => emulator.power_cycle(),
+            Start::SaveState(state) => emulator.load_state(state)?,
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", MAGIC, VERSION)?;
+        writeln!(f, "model {}", self.model)?;
+        writeln!(f, "rom {:08X}", self.rom_hash)?;
+        writeln!(f, "boot-rom {}", if self.boot_rom { "yes" } else { "no" })?;
+        match &self.start {
+            Start::PowerOn => writeln!(f, "start power-on")?,
+            Start::SaveState(state) => writeln!(f, "start state {}", base64_encode(state))?,
+        }
+        for input in &self.frames {
+            writeln!(f, "{}", input)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Movie {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut lines = s
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let last_line = s.lines().count();
+        let invalid = |line: usize, reason: String| Error::Invalid { line, reason };
+        let mut field = |name: &str| {
+            let (line, content) = lines
+                .next()
+                .ok_or_else(|| invalid(last_line, format!("missing {}", name)))?;
+            content
+                .strip_prefix(name)
+                .and_then(|value| value.strip_prefix(' '))
+                .map(|value| (line, value.to_owned()))
+                .ok_or_else(|| invalid(line, format!("expected {}", name)))
+        };
+
+        let (line, version) = field(MAGIC)?;
+        if version != VERSION.to_string() {
+            return Err(invalid(line, format!("unsupported version {}", version)));
+        }
+        let (line, model) = field("model")?;
+        let model = model
+            .parse::<Model>()
+            .map_err(|err| invalid(line, err.to_string()))?;
+        let (line, rom_hash) = field("rom")?;
+        let rom_hash = u32::from_str_radix(&rom_hash, 16)
+            .map_err(|err| invalid(line, format!("invalid ROM hash: {}", err)))?;
+        let (line, boot_rom) = field("boot-rom")?;
+        let boot_rom = match boot_rom.as_str() {
+            "yes" => true,
+            "no" => false,
+            _ => return Err(invalid(line, "expected boot-rom yes or no".to_owned())),
+        };
+        let (line, start) = field("start")?;
+        let start = match start.split_once(' ') {
+            None if start == "power-on" => Start::PowerOn,
+            Some(("state", state)) => Start::SaveState(
+                base64_decode(state).ok_or_else(|| invalid(line, "invalid state".to_owned()))?,
+            ),
+            _ => return Err(invalid(line, "expected start power-on or state".to_owned())),
+        };
+
+        let frames = lines
+            .map(|(line, input)| input.parse().map_err(|reason| invalid(line, reason)))
+            .collect::<Result<_>>()?;
+        let movie = Self {
+            model,
+            rom_hash,
+            boot_rom,
+            start,
+            frames,
+        };
+        if let Start::SaveState(state) = &movie.start {
+            SaveState::parse(state)?;
+        }
+        Ok(movie)
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64_ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3F] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes() {
+        let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+        bits = (bits << 6) | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests;
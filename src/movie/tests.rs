@@ -0,0 +1,126 @@
+use super::{base64_decode, base64_encode, Error, Input, Movie, Start};
+use crate::{joypad::DmgButton, model::Model, Emulator};
+
+/// ROM without an MBC that keeps copying the joypad register to work RAM.
+fn joypad_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // LD A, 0x20; LDH (0x00), A; LDH A, (0x00); LD (0xC000), A; JR -11
+    rom[0x0100..0x010B].copy_from_slice(&[
+        0x3E, 0x20, 0xE0, 0x00, 0xF0, 0x00, 0xEA, 0x00, 0xC0, 0x18, 0xF5,
+    ]);
+    rom
+}
+
+fn emulator() -> Emulator {
+    let mut emulator = Emulator::new(Model::Dmg, None);
+    emulator.load_rom_data(joypad_rom()).unwrap();
+    emulator
+}
+
+fn inputs() -> Vec<Input> {
+    [
+        &[][..],
+        &[DmgButton::Right],
+        &[DmgButton::Up, DmgButton::A],
+        &[DmgButton::Start, DmgButton::Select, DmgButton::B],
+        &[],
+    ]
+    .iter()
+    .map(|buttons| {
+        let mut input = Input::default();
+        for &button in *buttons {
+            input.set(button, true);
+        }
+        input
+    })
+    .collect()
+}
+
+/// Plays `movie` from its start and returns the state it ends in.
+fn play(movie: &Movie) -> Vec<u8> {
+    let mut emulator = emulator();
+    movie.start(&mut emulator).unwrap();
+    for input in &movie.frames {
+        emulator.set_buttons(&input.buttons());
+        emulator.run_frame();
+    }
+    emulator.save_state()
+}
+
+#[test]
+fn power_on_movie_round_trip() {
+    let mut movie = Movie::new(&emulator(), Start::PowerOn);
+    movie.frames = inputs();
+
+    let text = movie.to_string();
+    assert!(text.contains("start power-on\n|........|\n|...R....|\n|U......A|\n"));
+    let parsed: Movie = text.parse().unwrap();
+    assert_eq!(parsed.model, movie.model);
+    assert_eq!(parsed.rom_hash, movie.rom_hash);
+    assert_eq!(parsed.boot_rom, movie.boot_rom);
+    assert!(matches!(parsed.start, Start::PowerOn));
+    assert_eq!(parsed.frames, movie.frames);
+    assert_eq!(play(&parsed), play(&movie));
+}
+
+#[test]
+fn save_state_movie_round_trip() {
+    let mut emulator = emulator();
+    for _ in 0..3 {
+        emulator.run_frame();
+    }
+    let state = emulator.save_state();
+    let mut movie = Movie::new(&emulator, Start::SaveState(state.clone()));
+    movie.frames = inputs();
+
+    let parsed: Movie = movie.to_string().parse().unwrap();
+    match &parsed.start {
+        Start::SaveState(parsed_state) => assert_eq!(*parsed_state, state),
+        Start::PowerOn => panic!("movie starts from power on"),
+    }
+    assert_eq!(parsed.frames, movie.frames);
+    assert_eq!(play(&parsed), play(&movie));
+}
+
+#[test]
+fn base64_round_trip() {
+    for length in 0..=6 {
+        let data: Vec<u8> = (0..length).map(|byte| byte * 41 + 5).collect();
+        let text = base64_encode(&data);
+        assert_eq!(text.len() % 4, 0);
+        assert_eq!(base64_decode(&text), Some(data));
+    }
+    assert_eq!(base64_encode(b"dmg"), "ZG1n");
+    assert_eq!(base64_decode("ZG1n!"), None);
+}
+
+#[test]
+fn rejects_malformed_lines() {
+    let header = "dmg-rs movie 1\nmodel dmg\nrom 7BA62566\nboot-rom no\n";
+    let cases = [
+        ("dmg-rs movie 2\n".to_owned(), 1),
+        ("dmg-rs movie 1\nmodel gbc\n".to_owned(), 2),
+        ("dmg-rs movie 1\nmodel dmg\nrom XYZ\n".to_owned(), 3),
+        (
+            "dmg-rs movie 1\nmodel dmg\nrom 0\nboot-rom maybe\n".to_owned(),
+            4,
+        ),
+        (format!("{}start later\n", header), 5),
+        (format!("{}start state !!!!\n", header), 5),
+        (
+            format!("{}start power-on\n|........|\n|.......|\n", header),
+            7,
+        ),
+        (
+            format!("{}start power-on\n\n# comment\n|A.......|\n", header),
+            8,
+        ),
+        (header.to_owned(), 4),
+    ];
+    for (text, expected) in cases {
+        match text.parse::<Movie>() {
+            Err(Error::Invalid { line, .. }) => assert_eq!(line, expected, "{}", text),
+            other => panic!("{:?} parsing {}", other, text),
+        }
+    }
+}
@@ -1,15 +1,13 @@
-use std::{
-    cell::RefCell,
-    rc::Rc,
-    sync::{mpsc::Sender, Arc},
+use crate::{
+    clock::ClockTicks,
+    graphics::{self, Color},
+    interrupt::{Interrupt, InterruptController},
+    model::Model,
+    save_state::{self, Reader, Snapshot, Writer},
+    scheduler::{Event, Scheduler, Timestamp},
 };
 
-use eframe::epaint::Color32;
-use tracing::error;
-
-use crate::{dmg::ClockTicks, graphics, lr35902, mmu::MemoryMapUnit, thread::DmgMessage};
-
-pub type PixelBuffer = [Color32; 160 * 144];
+pub type PixelBuffer = [Color; 160 * 144];
 
 #[derive(Debug, Clone, Copy)]
 pub enum Mode {
@@ -21,11 +19,23 @@ pub enum Mode {
 
 #[derive(Debug)]
 pub struct PixelProcessingUnit {
-    mmu: Rc<RefCell<MemoryMapUnit>>,
+    vram: [u8; 0x2000],
+    oam: [u8; 0xA0],
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
     mode: Mode,
     pixel_buffer: PixelBuffer,
     line_to_draw: usize,
-    tx: Sender<DmgMessage>,
+    frame_ready: bool,
+    stat_write_bug: bool,
 }
 
 const LCDC_HBLANK: u8 = 1u8 << 3;
@@ -34,155 +44,253 @@ const LCDC_OAM: u8 = 1u8 << 5;
 const LCDC_LYC: u8 = 1u8 << 6;
 
 impl PixelProcessingUnit {
-    pub fn new(mmu: Rc<RefCell<MemoryMapUnit>>, tx: Sender<DmgMessage>) -> Self {
+    pub fn new(model: Model) -> Self {
         Self {
-            mmu,
+            vram: [0u8; 0x2000],
+            oam: [0u8; 0xA0],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
             mode: Mode::OAMSearch,
-            pixel_buffer: [Color32::WHITE; 160 * 144],
+            pixel_buffer: [Color::WHITE; 160 * 144],
             line_to_draw: 0,
-            tx,
+            frame_ready: false,
+            stat_write_bug: model.has_stat_write_bug(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn vram(&self) -> &[u8; 0x2000] {
+        &self.vram
+    }
+
+    pub fn oam(&self) -> &[u8; 0xA0] {
+        &self.oam
+    }
+
+    /// Frame being drawn, lines past the current one still hold the previous frame.
+    pub fn pixel_buffer(&self) -> &PixelBuffer {
+        &self.pixel_buffer
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[(address - 0x8000) as usize]
+    }
+
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        self.vram[(address - 0x8000) as usize] = value;
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[(address - 0xFE00) as usize]
+    }
+
+    pub fn write_oam(&mut self, address: u16, value: u8) {
+        self.oam[(address - 0xFE00) as usize] = value;
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            0xFF40 => self.lcdc,
+            0xFF41 => self.stat,
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.line_to_draw as u8,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_register(
+        &mut self,
+        address: u16,
+        value: u8,
+        interrupts: &mut InterruptController,
+    ) {
+        match address {
+            0xFF40 => self.lcdc = value,
+            0xFF41 => self.write_stat(value, interrupts),
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF44 => (),
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => unreachable!(),
         }
     }
 
-    pub fn step(&mut self) -> ClockTicks {
+    fn write_stat(&mut self, value: u8, interrupts: &mut InterruptController) {
+        self.stat = (self.stat & 0x07) | (value & 0x78);
+
+        // On monochrome models the write enables every STAT source for a cycle
+        if self.stat_write_bug && self.lcd_enabled() {
+            let coincidence = self.line_to_draw as u8 == self.lyc;
+            if matches!(self.mode, Mode::HBlank | Mode::VBlank) || coincidence {
+                interrupts.request(Interrupt::LcdStat);
+            }
+        }
+    }
+
+    /// Returns the last frame once it is complete.
+    pub fn take_frame(&mut self) -> Option<&PixelBuffer> {
+        if !self.frame_ready {
+            return None;
+        }
+        self.frame_ready = false;
+        Some(&self.pixel_buffer)
+    }
+
+    /// Schedules the first mode change, one T-cycle after power on.
+    pub fn start(&self, scheduler: &mut Scheduler) {
+        scheduler.schedule(Event::PpuMode, 1);
+    }
+
+    /// Handles the mode change scheduled at `time` and schedules the next one.
+    pub fn change_mode(
+        &mut self,
+        time: Timestamp,
+        interrupts: &mut InterruptController,
+        scheduler: &mut Scheduler,
+    ) {
+        let ticks = self.step(interrupts);
+        scheduler.schedule(Event::PpuMode, time + ticks as u64);
+    }
+
+    fn step(&mut self, interrupts: &mut InterruptController) -> ClockTicks {
         match self.mode {
-            Mode::OAMSearch => self.step_oam_search(),
-            Mode::PixelTransfer => self.step_pixel_transfer(),
-            Mode::HBlank => self.step_h_blank(),
-            Mode::VBlank => self.step_v_blank(),
+            Mode::OAMSearch => self.step_oam_search(interrupts),
+            Mode::PixelTransfer => self.step_pixel_transfer(interrupts),
+            Mode::HBlank => self.step_h_blank(interrupts),
+            Mode::VBlank => self.step_v_blank(interrupts),
         }
     }
 
-    fn trigger_interrupts(&self) {
-        let lcdc = self.mmu.borrow().read_8(0xFF41);
-        let mut int_flag = self.mmu.borrow().read_8(0xFF0F);
-        let int_enable = self.mmu.borrow().read_8(0xFFFF);
+    fn trigger_interrupts(&mut self, interrupts: &mut InterruptController) {
+        let lcdc = self.stat;
         match self.mode {
-            Mode::HBlank => {
-                if lcdc & LCDC_HBLANK != 0 && int_enable & lr35902::LCDBIT != 0 {
-                    int_flag |= lr35902::LCDBIT;
-                }
-            }
+            Mode::HBlank if lcdc & LCDC_HBLANK != 0 => interrupts.request(Interrupt::LcdStat),
             Mode::VBlank => {
-                if lcdc & LCDC_VBLANK != 0 && int_enable & lr35902::LCDBIT != 0 {
-                    int_flag |= lr35902::LCDBIT;
-                }
-                if int_enable & lr35902::VBLANKBIT != 0 {
-                    int_flag |= lr35902::VBLANKBIT;
-                }
-            }
-            Mode::OAMSearch => {
-                if lcdc & LCDC_OAM != 0 && int_enable & lr35902::LCDBIT != 0 {
-                    int_flag |= lr35902::LCDBIT;
+                if lcdc & LCDC_VBLANK != 0 {
+                    interrupts.request(Interrupt::LcdStat);
                 }
+                interrupts.request(Interrupt::VBlank);
             }
+            Mode::OAMSearch if lcdc & LCDC_OAM != 0 => interrupts.request(Interrupt::LcdStat),
             _ => (),
         };
-        self.mmu.borrow_mut().write_8(0xFF0F, int_flag);
     }
 
-    fn set_mode(&mut self, mode: Mode) {
+    fn set_mode(&mut self, mode: Mode, interrupts: &mut InterruptController) {
         self.mode = mode;
-        let stat = self.mmu.borrow().read_8(0xFF41);
-        let stat = (stat & 0xFC) | mode as u8;
-        self.mmu.borrow_mut().write_8(0xFF41, stat);
-        self.trigger_interrupts();
+        self.stat = (self.stat & 0xFC) | mode as u8;
+        self.trigger_interrupts(interrupts);
     }
 
-    fn step_oam_search(&mut self) -> ClockTicks {
-        self.set_mode(Mode::PixelTransfer);
-        80
-    }
+    fn set_line(&mut self, line: usize, interrupts: &mut InterruptController) {
+        self.line_to_draw = line;
 
-    fn step_pixel_transfer(&mut self) -> ClockTicks {
-        let ticks = self.draw_line();
-        if self.line_to_draw >= 144 {
-            if let Err(err) = self
-                .tx
-                .send(DmgMessage::Render(Arc::new(self.pixel_buffer.clone())))
-            {
-                error!("Could not send Pixel buffer to GUI: {:?}", err);
-            }
+        // Checks for LYC == LY to trigger interrupts
+        let lcdc = self.stat;
+        let ly = self.line_to_draw as u8;
+        if ly == self.lyc && lcdc & LCDC_LYC != 0 {
+            interrupts.request(Interrupt::LcdStat);
         }
-        self.set_mode(Mode::HBlank);
-        ticks
     }
 
-    fn step_h_blank(&mut self) -> ClockTicks {
+    // Each step ends the current mode and returns how long the next one lasts
+
+    fn step_oam_search(&mut self, interrupts: &mut InterruptController) -> ClockTicks {
+        self.set_mode(Mode::PixelTransfer, interrupts);
+        172
+    }
+
+    fn step_pixel_transfer(&mut self, interrupts: &mut InterruptController) -> ClockTicks {
+        self.draw_line();
+        self.set_mode(Mode::HBlank, interrupts);
+        204
+    }
+
+    fn step_h_blank(&mut self, interrupts: &mut InterruptController) -> ClockTicks {
+        self.set_line(self.line_to_draw + 1, interrupts);
         if self.line_to_draw >= 144 {
-            self.set_mode(Mode::VBlank);
+            self.frame_ready = true;
+            self.set_mode(Mode::VBlank, interrupts);
+            456
         } else {
-            self.set_mode(Mode::OAMSearch);
+            self.set_mode(Mode::OAMSearch, interrupts);
+            80
         }
-        204
     }
 
-    fn step_v_blank(&mut self) -> ClockTicks {
-        self.line_to_draw += 1;
-        if self.line_to_draw > 153 {
-            self.line_to_draw = 0;
-            self.set_mode(Mode::OAMSearch);
+    fn step_v_blank(&mut self, interrupts: &mut InterruptController) -> ClockTicks {
+        if self.line_to_draw >= 153 {
+            self.set_line(0, interrupts);
+            self.set_mode(Mode::OAMSearch, interrupts);
+            80
+        } else {
+            self.set_line(self.line_to_draw + 1, interrupts);
+            456
         }
-        self.mmu
-            .borrow_mut()
-            .write_8(0xFF44, self.line_to_draw as u8);
-        456
     }
 
     // #[tracing::instrument]
-    fn draw_line(&mut self) -> ClockTicks {
-        // TODO: Suboptimal hack used to facilitate image drawing
-        // Potential bottleneck
-
+    fn draw_line(&mut self) {
         // Step 1: Draw the background
-        let vram = self.mmu.borrow().vram();
-        self.draw_bg_line(&vram);
+        self.draw_bg_line();
 
         // Step 2: Draw the sprites
 
         // Step 3: Draw the window
-
-        self.line_to_draw += 1;
-        self.mmu
-            .borrow_mut()
-            .write_8(0xFF44, self.line_to_draw as u8);
-
-        // Checks for LYC == LY to trigger interrupts
-        let mut int_flag = self.mmu.borrow().read_8(0xFF0F);
-        let lcdc = self.mmu.borrow().read_8(0xFF41);
-        let int_enable = self.mmu.borrow().read_8(0xFFFF);
-        let ly = self.mmu.borrow().read_8(0xFF44);
-        let lyc = self.mmu.borrow().read_8(0xFF45);
-        if ly == lyc && lcdc & LCDC_LYC != 0 && int_enable & lr35902::LCDBIT != 0 {
-            int_flag |= lr35902::LCDBIT;
-        }
-        self.mmu.borrow_mut().write_8(0xFF0F, int_flag);
-        172
     }
 
-    fn draw_bg_line(&mut self, vram: &[u8]) {
-        let lcdc = self.mmu.borrow().read_8(0xFF40);
-        let tile_data;
-        if lcdc & 0b00010000 != 0 {
-            tile_data = &vram[0x0000..=0x0FFF];
+    fn draw_bg_line(&mut self) {
+        let vram = &self.vram;
+        let lcdc = self.lcdc;
+        let tile_data = if lcdc & 0b00010000 != 0 {
+            &vram[0x0000..=0x0FFF]
         } else {
-            tile_data = &vram[0x0800..=0x17FF];
+            &vram[0x0800..=0x17FF]
             // tile_data = &vram[0x0000..=0x17FF];
-        }
+        };
 
         let bg_data = &vram[0x1800..=0x1BFF];
-        let palette = graphics::ColorPalette::from_dmg_palette(self.mmu.borrow().read_8(0xFF47));
-        let scy = self.mmu.borrow().read_8(0xFF42) as usize;
-        let scx = self.mmu.borrow().read_8(0xFF43) as usize;
-        let line_y = scy + self.line_to_draw;
+        let palette = graphics::ColorPalette::from_dmg_palette(self.bgp);
+        let scy = self.scy as usize;
+        let scx = self.scx as usize;
+        // The background map is 256x256 pixels and wraps around on both axes
+        let line_y = (scy + self.line_to_draw) & 0xFF;
         let pixel_line =
             &mut self.pixel_buffer[(self.line_to_draw * 160)..((self.line_to_draw + 1) * 160)];
-        for i in 0..160 {
-            let bg_tile = bg_data[(line_y / 8) * 32 + ((scx + i) / 8)] as usize;
+        for (i, pixel) in pixel_line.iter_mut().enumerate() {
+            let x = (scx + i) & 0xFF;
+            let bg_tile = bg_data[(line_y / 8) * 32 + (x / 8) % 32] as usize;
 
             let tile_array = &tile_data[(bg_tile * 16)..(bg_tile * 16 + 16)];
             let tile_px_y = line_y % 8;
-            let tile_px_x = (scx + i) % 8;
+            let tile_px_x = x % 8;
 
             let px_byte_a = tile_array[tile_px_y * 2];
             let px_byte_b = tile_array[tile_px_y * 2 + 1];
@@ -191,8 +299,61 @@ impl PixelProcessingUnit {
             let bit_b = (px_byte_b.wrapping_shr(7 - tile_px_x as u32)) & 0x01;
             let color = (bit_b << 1) | bit_a;
 
-            pixel_line[i] = palette[color as usize];
+            *pixel = palette[color as usize];
         }
         // Determine the tile byte given the coordinate of pixel
     }
 }
+
+impl Snapshot for PixelProcessingUnit {
+    fn save(&self, state: &mut Writer) {
+        state.bytes(&self.vram);
+        state.bytes(&self.oam);
+        for register in [
+            self.lcdc, self.stat, self.scy, self.scx, self.lyc, self.bgp, self.obp0, self.obp1,
+            self.wy, self.wx,
+        ] {
+            state.u8(register);
+        }
+        state.u8(self.mode as u8);
+        state.pixels(&self.pixel_buffer);
+        state.u8(self.line_to_draw as u8);
+        state.bool(self.frame_ready);
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        state.bytes(&mut self.vram)?;
+        state.bytes(&mut self.oam)?;
+        for register in [
+            &mut self.lcdc,
+            &mut self.stat,
+            &mut self.scy,
+            &mut self.scx,
+            &mut self.lyc,
+            &mut self.bgp,
+            &mut self.obp0,
+            &mut self.obp1,
+            &mut self.wy,
+            &mut self.wx,
+        ] {
+            *register = state.u8()?;
+        }
+        self.mode = match state.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OAMSearch,
+            3 => Mode::PixelTransfer,
+            _ => return Err(save_state::Error::Invalid("unknown PPU mode")),
+        };
+        state.pixels(&mut self.pixel_buffer)?;
+        self.line_to_draw = state.u8()? as usize;
+        if self.line_to_draw > 153 {
+            return Err(save_state::Error::Invalid("LY out of range"));
+        }
+        self.frame_ready = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,37 @@
+use super::PixelProcessingUnit;
+use crate::{interrupt::InterruptController, model::Model};
+
+/// Runs the PPU until it completes a frame.
+fn render_frame(ppu: &mut PixelProcessingUnit, interrupts: &mut InterruptController) {
+    while ppu.take_frame().is_none() {
+        ppu.step(interrupts);
+    }
+}
+
+#[test]
+fn background_wraps_around_the_map() {
+    let mut ppu = PixelProcessingUnit::new(Model::Dmg);
+    let mut interrupts = InterruptController::new();
+    // Tile 1 is solid color 3, and only the last row and column of the map use it
+    for byte in &mut ppu.vram[0x0010..0x0020] {
+        *byte = 0xFF;
+    }
+    for index in 0..32 {
+        ppu.vram[0x1800 + 31 * 32 + index] = 1;
+        ppu.vram[0x1800 + index * 32 + 31] = 1;
+    }
+    ppu.write_register(0xFF40, 0x91, &mut interrupts);
+    ppu.write_register(0xFF47, 0xE4, &mut interrupts);
+    ppu.write_register(0xFF42, 0xFF, &mut interrupts);
+    ppu.write_register(0xFF43, 0xFF, &mut interrupts);
+
+    render_frame(&mut ppu, &mut interrupts);
+
+    let pixels = ppu.pixel_buffer();
+    let dark = pixels[0];
+    // Line 0 shows the last pixel row of the map, then line 1 wraps to the first one
+    assert!(pixels[..160].iter().all(|&color| color == dark));
+    assert_ne!(pixels[160 + 1], dark);
+    // Column 0 shows the last pixel column of the map on every line
+    assert!((0..144).all(|line| pixels[line * 160] == dark));
+}
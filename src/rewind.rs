@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+/// Snapshots of the last frames, to play the game backwards.
+///
+/// Only the newest state is kept whole. Every older one is stored as the difference with the
+/// state that followed it, which is mostly zeros from one frame to the next and gets run length
+/// encoded. Going back applies the newest difference to the newest state, so the oldest
+/// differences can be dropped once the buffer is full without decoding anything.
+#[derive(Debug)]
+pub struct Rewind {
+    depth: usize,
+    newest: Option<Vec<u8>>,
+    /// Differences from oldest to newest, each turning a state into the previous one.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Keeps up to `depth` states.
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            newest: None,
+            deltas: VecDeque::with_capacity(depth),
+        }
+    }
+
+    /// States kept at most.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes used by the states.
+    pub fn memory_usage(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, Vec::len);
+        newest + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.depth == 0 {
+            return;
+        }
+        if let Some(previous) = self.newest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+        }
+        self.newest = Some(state);
+        while self.len() > self.depth {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Removes and returns the newest state. The one before it becomes the newest.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        self.newest = self
+            .deltas
+            .pop_back()
+            .map(|delta| decode_delta(&newest, &delta));
+        Some(newest)
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_length(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(&byte) = data.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+/// Encodes what turns `from` into `to`: the length of `to`, then the XOR of both as pairs of
+/// unchanged and changed byte runs. `from` reads as zeros past its end.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor = |index: usize| to[index] ^ from.get(index).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    write_length(&mut out, to.len());
+
+    let mut index = 0;
+    while index < to.len() {
+        let unchanged_start = index;
+        while index < to.len() && xor(index) == 0 {
+            index += 1;
+        }
+        let changed_start = index;
+        while index < to.len() && xor(index) != 0 {
+            index += 1;
+        }
+        write_length(&mut out, changed_start - unchanged_start);
+        write_length(&mut out, index - changed_start);
+        out.extend((changed_start..index).map(xor));
+    }
+    out
+}
+
+fn decode_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = read_length(delta, &mut position);
+    let mut to = from.to_vec();
+    to.resize(length, 0);
+
+    let mut index = 0;
+    while position < delta.len() {
+        index += read_length(delta, &mut position);
+        let changed = read_length(delta, &mut position);
+        for (byte, change) in to[index..index + changed]
+            .iter_mut()
+            .zip(&delta[position..position + changed])
+        {
+            *byte ^= change;
+        }
+        index += changed;
+        position += changed;
+    }
+    to
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,62 @@
+use super::{decode_delta, encode_delta, Rewind};
+
+fn round_trip(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let delta = encode_delta(from, to);
+    assert_eq!(decode_delta(from, &delta), to);
+    delta
+}
+
+#[test]
+fn identical_states() {
+    let state: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let delta = round_trip(&state, &state);
+    // The length of the state, then a single unchanged run
+    assert_eq!(delta, [0xE8, 0x07, 0xE8, 0x07, 0x00]);
+}
+
+#[test]
+fn fully_different_states() {
+    let from = vec![0x00; 300];
+    let to = vec![0xFF; 300];
+    let delta = round_trip(&from, &to);
+    assert_eq!(delta.len(), 2 + 1 + 2 + 300);
+}
+
+#[test]
+fn runs_longer_than_a_length_byte() {
+    let from = vec![0x55; 70_000];
+    let mut to = from.clone();
+    // Unchanged and changed runs that need two and three length bytes
+    for byte in &mut to[200..400] {
+        *byte = 0xAA;
+    }
+    for byte in &mut to[500..20_000] {
+        *byte ^= 0x01;
+    }
+    to[69_999] = 0;
+    round_trip(&from, &to);
+}
+
+#[test]
+fn states_of_different_lengths() {
+    let short: Vec<u8> = (1..=100).collect();
+    let mut long: Vec<u8> = (1..=250).collect();
+    long[10] = 0;
+    round_trip(&short, &long);
+    round_trip(&long, &short);
+    round_trip(&[], &long);
+    round_trip(&long, &[]);
+}
+
+#[test]
+fn pops_states_newest_first_and_drops_the_oldest() {
+    let mut rewind = Rewind::new(3);
+    for frame in 0..5u8 {
+        rewind.push(vec![frame; 16 + frame as usize]);
+    }
+    assert_eq!(rewind.len(), 3);
+    for frame in (2..5u8).rev() {
+        assert_eq!(rewind.pop(), Some(vec![frame; 16 + frame as usize]));
+    }
+    assert_eq!(rewind.pop(), None);
+}
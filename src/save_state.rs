@@ -0,0 +1,384 @@
+//! Versioned binary snapshots of the whole machine.
+//!
+//! A save state starts with a header identifying the emulator version, the ROM and the model
+//! it was taken with, followed by a thumbnail of the screen. The rest is one section per
+//! component, each made of a 4 byte tag, a 32 bit length and the component's own encoding.
+//! Every integer is little endian.
+//!
+//! The sections alone, without the header, make a snapshot that is only meant to be loaded back
+//! into the machine it was taken on, such as the states kept to rewind.
+//!
+//! Sound registers are kept with the rest of the I/O registers in the MMU section, as there is
+//! no APU state beyond them yet.
+
+use std::{fmt, io, result};
+
+use thiserror::Error;
+
+use crate::{graphics::Color, model::Model, ppu::PixelBuffer};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Could not read save state: {0}.")]
+    Loading(#[from] io::Error),
+    #[error("Not a save state.")]
+    InvalidMagic,
+    #[error("Save state format version {0} is not supported.")]
+    UnsupportedVersion(u16),
+    #[error("Save state was taken with another ROM (hash {found:08X}, expected {expected:08X}).")]
+    RomMismatch { expected: u32, found: u32 },
+    #[error("Save state was taken on {found}, the emulated model is {expected}.")]
+    ModelMismatch { expected: Model, found: Model },
+    #[error("Save state is truncated.")]
+    Truncated,
+    #[error("Save state has no {0} section.")]
+    MissingSection(Section),
+    #[error("Invalid save state: {0}.")]
+    Invalid(&'static str),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+const MAGIC: &[u8; 8] = b"DMGSTATE";
+
+/// Bumped whenever the encoding of a section changes.
+pub const FORMAT_VERSION: u16 = 2;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0xEDB8_8320,
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// CRC-32 of a ROM image, identifying the game a save state belongs to.
+pub fn rom_hash(rom: &[u8]) -> u32 {
+    crc32(rom)
+}
+
+/// Component a section of the save state belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Cpu,
+    /// Memory, I/O registers, interrupts and the peripherals without a section of their own.
+    Mmu,
+    Ppu,
+    Timer,
+    Cartridge,
+}
+
+impl Section {
+    pub const ALL: [Section; 5] = [
+        Section::Cpu,
+        Section::Mmu,
+        Section::Ppu,
+        Section::Timer,
+        Section::Cartridge,
+    ];
+
+    fn tag(self) -> &'static [u8; 4] {
+        match self {
+            Section::Cpu => b"CPU ",
+            Section::Mmu => b"MMU ",
+            Section::Ppu => b"PPU ",
+            Section::Timer => b"TIMR",
+            Section::Cartridge => b"CART",
+        }
+    }
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Section::Cpu => "CPU",
+            Section::Mmu => "MMU",
+            Section::Ppu => "PPU",
+            Section::Timer => "timer",
+            Section::Cartridge => "cartridge",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// State a component can be saved to and restored from.
+///
+/// Loading happens in place, so settings that are not part of the state, such as the model,
+/// are kept.
+pub trait Snapshot {
+    fn save(&self, state: &mut Writer);
+    fn load(&mut self, state: &mut Reader) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    /// Data whose size is known when reading it back.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Data prefixed with its size.
+    pub fn vec(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub fn pixels(&mut self, pixels: &PixelBuffer) {
+        for color in pixels {
+            self.bytes(&[color.r, color.g, color.b]);
+        }
+    }
+
+    /// Writes what `write` saves as the given section.
+    pub fn section(&mut self, section: Section, write: impl FnOnce(&mut Writer)) {
+        let mut content = Writer::new();
+        write(&mut content);
+        self.bytes(section.tag());
+        self.vec(&content.data);
+    }
+}
+
+#[derive(Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.data.len() < length {
+            return Err(Error::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Invalid("boolean out of range")),
+        }
+    }
+
+    /// Fills `bytes` with data written by `Writer::bytes`.
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> Result<()> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    pub fn vec(&mut self) -> Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    pub fn pixels(&mut self, pixels: &mut PixelBuffer) -> Result<()> {
+        for color in pixels.iter_mut() {
+            let [r, g, b] = self.array()?;
+            *color = Color::from_rgb(r, g, b);
+        }
+        Ok(())
+    }
+
+    /// Sections following the header, by component.
+    fn sections(mut self) -> Result<Sections<'a>> {
+        let mut sections = Vec::new();
+        while !self.data.is_empty() {
+            let tag = self.array::<4>()?;
+            let length = self.u32()? as usize;
+            let content = self.take(length)?;
+            // Sections added by later versions are skipped
+            if let Some(section) = Section::ALL.into_iter().find(|s| *s.tag() == tag) {
+                sections.push((section, content));
+            }
+        }
+        Ok(Sections { sections })
+    }
+}
+
+/// What a save state was taken with, readable without loading it.
+#[derive(Debug)]
+pub struct Header {
+    pub emulator_version: String,
+    pub rom_hash: u32,
+    pub model: Model,
+    /// Screen when the state was saved.
+    pub thumbnail: Box<PixelBuffer>,
+}
+
+impl Header {
+    pub fn new(rom_hash: u32, model: Model, thumbnail: &PixelBuffer) -> Self {
+        Self {
+            emulator_version: env!("CARGO_PKG_VERSION").to_owned(),
+            rom_hash,
+            model,
+            thumbnail: Box::new(*thumbnail),
+        }
+    }
+
+    /// Reads the header at the start of a save state.
+    pub fn read(data: &[u8]) -> Result<Self> {
+        Self::read_from(&mut Reader::new(data))
+    }
+
+    fn read_from(state: &mut Reader) -> Result<Self> {
+        if state.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(Error::InvalidMagic);
+        }
+        let version = state.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let emulator_version = String::from_utf8(state.vec()?)
+            .map_err(|_| Error::Invalid("emulator version is not UTF-8"))?;
+        let rom_hash = state.u32()?;
+        let model = String::from_utf8_lossy(&state.vec()?)
+            .parse::<Model>()
+            .map_err(|_| Error::Invalid("unknown model"))?;
+        let mut thumbnail = Box::new([Color::WHITE; 160 * 144]);
+        state.pixels(&mut thumbnail)?;
+        Ok(Self {
+            emulator_version,
+            rom_hash,
+            model,
+            thumbnail,
+        })
+    }
+
+    fn write(&self, state: &mut Writer) {
+        state.bytes(MAGIC);
+        state.u16(FORMAT_VERSION);
+        state.vec(self.emulator_version.as_bytes());
+        state.u32(self.rom_hash);
+        state.vec(self.model.to_string().as_bytes());
+        state.pixels(&self.thumbnail);
+    }
+}
+
+/// A save state being written, header first.
+pub fn writer(header: &Header) -> Writer {
+    let mut state = Writer::new();
+    header.write(&mut state);
+    state
+}
+
+/// A save state being read, split into its header and its sections.
+#[derive(Debug)]
+pub struct SaveState<'a> {
+    pub header: Header,
+    pub sections: Sections<'a>,
+}
+
+impl<'a> SaveState<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let mut state = Reader::new(data);
+        let header = Header::read_from(&mut state)?;
+        let sections = state.sections()?;
+        Ok(Self { header, sections })
+    }
+}
+
+/// Content of a save state by component.
+#[derive(Debug)]
+pub struct Sections<'a> {
+    sections: Vec<(Section, &'a [u8])>,
+}
+
+impl<'a> Sections<'a> {
+    /// Reads sections written without a header, as in a snapshot.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        Reader::new(data).sections()
+    }
+
+    /// Reader over the content of a section.
+    pub fn section(&self, section: Section) -> Result<Reader<'a>> {
+        self.content(section).map(Reader::new)
+    }
+
+    /// Encoded content of a section.
+    pub fn content(&self, section: Section) -> Result<&'a [u8]> {
+        self.sections
+            .iter()
+            .find(|(s, _)| *s == section)
+            .map(|&(_, content)| content)
+            .ok_or(Error::MissingSection(section))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,96 @@
+use super::{Error, FORMAT_VERSION};
+use crate::{model::Model, Emulator};
+
+/// ROM without an MBC that counts in A and stores it to work RAM forever.
+fn counter_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // INC A; LD (0xC000), A; JR -6
+    rom[0x0100..0x0106].copy_from_slice(&[0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xFA]);
+    rom
+}
+
+fn emulator(rom: Vec<u8>) -> Emulator {
+    let mut emulator = Emulator::new(Model::Dmg, None);
+    emulator.load_rom_data(rom).unwrap();
+    emulator
+}
+
+fn run_frames(emulator: &mut Emulator, frames: usize) {
+    for _ in 0..frames {
+        emulator.run_frame();
+    }
+}
+
+#[test]
+fn loads_back_what_was_saved() {
+    let mut emulator = emulator(counter_rom());
+    run_frames(&mut emulator, 10);
+    let state = emulator.save_state();
+    run_frames(&mut emulator, 10);
+    let expected = emulator.save_state();
+
+    run_frames(&mut emulator, 5);
+    emulator.load_state(&state).unwrap();
+    assert_eq!(emulator.save_state(), state);
+    run_frames(&mut emulator, 10);
+    assert_eq!(emulator.save_state(), expected);
+}
+
+/// Loads `state` and checks that the machine was left untouched.
+fn load_rejected(emulator: &mut Emulator, state: &[u8]) -> Error {
+    let before = emulator.save_state();
+    let err = emulator.load_state(state).unwrap_err();
+    assert_eq!(emulator.save_state(), before);
+    err
+}
+
+#[test]
+fn rejects_truncated_states() {
+    let mut emulator = emulator(counter_rom());
+    let state = emulator.save_state();
+    run_frames(&mut emulator, 1);
+    for length in [0, 12, 100, state.len() / 2, state.len() - 1] {
+        let err = load_rejected(&mut emulator, &state[..length]);
+        assert!(
+            matches!(err, Error::Truncated | Error::InvalidMagic),
+            "{} bytes: {}",
+            length,
+            err
+        );
+    }
+}
+
+#[test]
+fn rejects_a_bad_magic() {
+    let mut emulator = emulator(counter_rom());
+    let mut state = emulator.save_state();
+    state[0] = b'X';
+    assert!(matches!(
+        load_rejected(&mut emulator, &state),
+        Error::InvalidMagic
+    ));
+}
+
+#[test]
+fn rejects_other_format_versions() {
+    let mut emulator = emulator(counter_rom());
+    let mut state = emulator.save_state();
+    state[8..10].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert!(matches!(
+        load_rejected(&mut emulator, &state),
+        Error::UnsupportedVersion(version) if version == FORMAT_VERSION + 1
+    ));
+}
+
+#[test]
+fn rejects_states_of_another_rom() {
+    let state = emulator(counter_rom()).save_state();
+    let mut rom = counter_rom();
+    rom[0x0200] = 0xFF;
+    let mut other = emulator(rom);
+    let expected = other.rom_hash();
+    assert!(matches!(
+        load_rejected(&mut other, &state),
+        Error::RomMismatch { expected: hash, .. } if hash == expected
+    ));
+}
@@ -0,0 +1,107 @@
+use crate::save_state::{self, Reader, Snapshot, Writer};
+
+/// Number of T-cycles since the machine was powered on.
+pub type Timestamp = u64;
+
+/// Something a component needs to do at a known time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The PPU enters its next mode.
+    PpuMode,
+    /// TIMA overflows and gets reloaded from TMA.
+    TimerOverflow,
+    /// The serial port has shifted out its last bit.
+    SerialTransfer,
+}
+
+impl Event {
+    const ALL: [Event; 3] = [Event::PpuMode, Event::TimerOverflow, Event::SerialTransfer];
+    const COUNT: usize = Event::ALL.len();
+}
+
+/// Keeps the time of the next occurrence of every event.
+///
+/// Components schedule their next event instead of being ticked every cycle, and are only run
+/// once the system clock reaches it. Between events, their registers are derived from the
+/// current timestamp when accessed.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    now: Timestamp,
+    events: [Option<Timestamp>; Event::COUNT],
+    /// Earliest scheduled event, to check for due events with a single comparison.
+    next: Option<Timestamp>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> Timestamp {
+        self.now
+    }
+
+    /// Schedules `event` at `time`, replacing its previous occurrence.
+    pub fn schedule(&mut self, event: Event, time: Timestamp) {
+        self.events[event as usize] = Some(time);
+        self.update_next();
+    }
+
+    /// Schedules `event` `cycles` T-cycles from now.
+    pub fn schedule_in(&mut self, event: Event, cycles: u64) {
+        self.schedule(event, self.now + cycles);
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.events[event as usize] = None;
+        self.update_next();
+    }
+
+    fn update_next(&mut self) {
+        self.next = self.events.iter().flatten().copied().min();
+    }
+
+    pub fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    /// Removes and returns the earliest event due by now, with the time it was scheduled at.
+    ///
+    /// Events scheduled at the same time are returned in declaration order.
+    pub fn pop_due(&mut self) -> Option<(Event, Timestamp)> {
+        if self.next? > self.now {
+            return None;
+        }
+
+        let (index, time) = self
+            .events
+            .iter()
+            .enumerate()
+            .filter_map(|(index, time)| time.map(|time| (index, time)))
+            .min_by_key(|&(_, time)| time)?;
+        self.events[index] = None;
+        self.update_next();
+        Some((Event::ALL[index], time))
+    }
+}
+
+impl Snapshot for Scheduler {
+    fn save(&self, state: &mut Writer) {
+        state.u64(self.now);
+        for time in self.events {
+            state.bool(time.is_some());
+            state.u64(time.unwrap_or_default());
+        }
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        self.now = state.u64()?;
+        for time in self.events.iter_mut() {
+            let scheduled = state.bool()?;
+            let at = state.u64()?;
+            *time = scheduled.then_some(at);
+        }
+        self.update_next();
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+    result,
+};
+
+use dmg_rs::{
+    boot_rom::BootRom,
+    graphics::Color,
+    joypad::{DmgButton, UnknownButton},
+    model::Model,
+    ppu::PixelBuffer,
+    Emulator,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Could not read input script: {0}.")]
+    LoadingScript(#[from] io::Error),
+    #[error("Input script line {line}: {reason}.")]
+    InvalidScript { line: usize, reason: String },
+    #[error("Could not decode reference image: {0}.")]
+    Decoding(#[from] png::DecodingError),
+    #[error("Could not write diff image: {0}.")]
+    Encoding(#[from] png::EncodingError),
+    #[error("Reference image is {width}x{height}, expected 160x144.")]
+    InvalidSize { width: u32, height: u32 },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+const DIFF_COLOR: [u8; 3] = [0xFF, 0x00, 0x00];
+
+/// Button presses and releases to replay, each on a given frame.
+///
+/// The script has one event per line, `FRAME press|release BUTTON`. Empty lines and lines
+/// starting with `#` are ignored:
+///
+/// ```text
+/// # Skip the title screen
+/// 120 press start
+/// 125 release start
+/// ```
+#[derive(Debug, Default)]
+pub struct InputScript {
+    events: Vec<(usize, DmgButton, bool)>,
+}
+
+impl InputScript {
+    pub fn from_file(path: &str) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    fn apply(&self, frame: usize, game: &mut Emulator) {
+        let events = self.events.iter().filter(|(at, _, _)| *at == frame);
+        for &(_, button, pressed) in events {
+            match pressed {
+                true => game.press_button(button),
+                false => game.release_button(button),
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for InputScript {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| Error::InvalidScript {
+                line: index + 1,
+                reason,
+            };
+
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [frame, action, button] = fields[..] else {
+                return Err(invalid("expected FRAME press|release BUTTON".to_owned()));
+            };
+            let frame = frame
+                .parse::<usize>()
+                .map_err(|err| invalid(format!("invalid frame {}: {}", frame, err)))?;
+            let pressed = match action {
+                "press" => true,
+                "release" => false,
+                _ => return Err(invalid(format!("unknown action {}", action))),
+            };
+            let button = button
+                .parse::<DmgButton>()
+                .map_err(|err: UnknownButton| invalid(err.to_string()))?;
+            events.push((frame, button, pressed));
+        }
+        Ok(Self { events })
+    }
+}
+
+/// Shade of a pixel from 0 (lightest) to 3 (darkest), so that frames drawn with our palette
+/// can be compared with references using another one, such as the grayscale dmg-acid2 image.
+fn shade(red: u8, green: u8, blue: u8) -> u8 {
+    let luma = (red as u32 * 299 + green as u32 * 587 + blue as u32 * 114) / 1000;
+    match luma {
+        0xD5.. => 0,
+        0x80..=0xD4 => 1,
+        0x2B..=0x7F => 2,
+        _ => 3,
+    }
+}
+
+/// Loads a 160x144 PNG as shades.
+fn load_reference(path: &Path) -> Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path).map_err(png::DecodingError::from)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    if info.width as usize != WIDTH || info.height as usize != HEIGHT {
+        return Err(Error::InvalidSize {
+            width: info.width,
+            height: info.height,
+        });
+    }
+
+    let samples = info.color_type.samples();
+    let shades = data[..info.buffer_size()]
+        .chunks_exact(samples)
+        .map(|pixel| match info.color_type {
+            png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                shade(pixel[0], pixel[0], pixel[0])
+            }
+            _ => shade(pixel[0], pixel[1], pixel[2]),
+        })
+        .collect();
+    Ok(shades)
+}
+
+/// Writes the frame with the pixels that differ from the reference in red.
+fn write_diff(path: &Path, frame: &PixelBuffer, mismatches: &[bool]) -> Result<()> {
+    let file = File::create(path).map_err(png::EncodingError::from)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = frame
+        .iter()
+        .zip(mismatches)
+        .flat_map(|(color, &mismatch)| match mismatch {
+            true => DIFF_COLOR,
+            false => [color.r, color.g, color.b],
+        })
+        .collect();
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+/// Runs `frames` frames of the ROM while replaying the input script, and compares the last
+/// frame drawn with the reference image. On mismatch, a diff image is written to `diff_path`.
+///
+/// Returns whether the frame matches the reference.
+pub fn compare(
+    rom_path: &str,
+    model: Model,
+    boot_rom: Option<BootRom>,
+    frames: usize,
+    script: &InputScript,
+    reference_path: &Path,
+    diff_path: &Path,
+) -> anyhow::Result<bool> {
+    let reference = load_reference(reference_path)?;
+    let mut game = Emulator::new(model, boot_rom);
+    game.load_rom(rom_path)?;
+
+    // Frames are counted in emulated time so that they keep going while the LCD is off
+    let mut last_frame = Box::new([Color::WHITE; WIDTH * HEIGHT]);
+    for frame in 0..frames {
+        script.apply(frame, &mut game);
+        if let Some(pixels) = game.run_frame().frame {
+            last_frame = pixels;
+        }
+    }
+
+    let mismatches: Vec<bool> = last_frame
+        .iter()
+        .zip(&reference)
+        .map(|(color, &expected)| shade(color.r, color.g, color.b) != expected)
+        .collect();
+    let count = mismatches.iter().filter(|&&mismatch| mismatch).count();
+    if count == 0 {
+        println!("PASS {} matches {}", rom_path, reference_path.display());
+        return Ok(true);
+    }
+
+    write_diff(diff_path, &last_frame, &mismatches)?;
+    println!(
+        "FAIL {} differs from {} on {} pixels, diff written to {}",
+        rom_path,
+        reference_path.display(),
+        count,
+        diff_path.display()
+    );
+    Ok(false)
+}
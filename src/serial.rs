@@ -0,0 +1,74 @@
+use crate::{
+    save_state::{self, Reader, Snapshot, Writer},
+    scheduler::{Event, Scheduler},
+};
+
+/// Serial port, without a link cable attached.
+///
+/// A transfer started with the internal clock shifts SB out one bit every 512 T-cycles (8192 Hz)
+/// and shifts in 1s, as nothing drives the line. Every byte sent is kept so test ROMs reporting
+/// over serial can be read back. Transfers waiting on an external clock never complete.
+#[derive(Debug, Default)]
+pub struct Serial {
+    data: u8,
+    control: u8,
+    output: Vec<u8>,
+}
+
+const TRANSFER_START: u8 = 0x80;
+const INTERNAL_CLOCK: u8 = 0x01;
+const TRANSFER_CYCLES: u64 = 8 * 512;
+
+impl Serial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles the end of a transfer, the serial interrupt is to be requested.
+    pub fn complete_transfer(&mut self) {
+        self.data = 0xFF;
+        self.control &= !TRANSFER_START;
+    }
+
+    pub fn write_8(&mut self, address: u16, value: u8, scheduler: &mut Scheduler) {
+        match address {
+            0xFF01 => self.data = value,
+            0xFF02 => {
+                self.control = value;
+                let start = TRANSFER_START | INTERNAL_CLOCK;
+                if value & start == start {
+                    self.output.push(self.data);
+                    scheduler.schedule_in(Event::SerialTransfer, TRANSFER_CYCLES);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn read_8(&self, address: u16) -> u8 {
+        match address {
+            0xFF01 => self.data,
+            0xFF02 => self.control,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Bytes sent since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+/// Bytes already sent are not part of the state.
+impl Snapshot for Serial {
+    fn save(&self, state: &mut Writer) {
+        state.u8(self.data);
+        state.u8(self.control);
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        self.data = state.u8()?;
+        self.control = state.u8()?;
+        Ok(())
+    }
+}
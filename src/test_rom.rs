@@ -0,0 +1,204 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use dmg_rs::{
+    boot_rom::BootRom,
+    clock::ClockTicks,
+    emulator::CYCLES_PER_FRAME,
+    lr35902::{Register16, Register8},
+    model::Model,
+    Emulator,
+};
+
+/// Opcode of `LD B,B`, the breakpoint Mooneye tests execute once done.
+const LD_B_B: u8 = 0x40;
+
+const MOONEYE_REGISTERS: [Register8; 6] = [
+    Register8::B,
+    Register8::C,
+    Register8::D,
+    Register8::E,
+    Register8::H,
+    Register8::L,
+];
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+/// Blargg tests write their status at 0xA000 once this signature is at 0xA001.
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+const BLARGG_RESET_REQUESTED: u8 = 0x81;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    TimedOut,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        *self == Outcome::Passed
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Outcome::Passed => "PASS",
+            Outcome::Failed(_) => "FAIL",
+            Outcome::TimedOut => "TIMEOUT",
+        };
+        f.pad(name)
+    }
+}
+
+/// Runs a test ROM until it reports a result, or until `max_cycles` T-cycles have run.
+///
+/// Blargg tests report over the serial port or through the signature at 0xA000, Mooneye tests
+/// execute `LD B,B` with a Fibonacci sequence in B, C, D, E, H and L when they pass.
+pub fn run(
+    path: &Path,
+    model: Model,
+    boot_rom: Option<BootRom>,
+    max_cycles: ClockTicks,
+) -> anyhow::Result<Outcome> {
+    let mut game = Emulator::new(model, boot_rom);
+    game.load_rom(&path.to_string_lossy())?;
+    let mut serial = Vec::new();
+    let mut cycles: ClockTicks = 0;
+    let mut next_frame = CYCLES_PER_FRAME;
+
+    while cycles < max_cycles {
+        let pc = game.registers().get_16(Register16::PC);
+        let opcode = game.peek_8(pc);
+        cycles += game.step_instruction();
+
+        if let Some(fault) = game.take_fault() {
+            return Ok(Outcome::Failed(fault.to_string()));
+        }
+        // The opcode was only executed if the CPU did not service an interrupt instead
+        if opcode == LD_B_B && game.registers().get_16(Register16::PC) == pc.wrapping_add(1) {
+            if let Some(outcome) = mooneye_outcome(&game) {
+                return Ok(outcome);
+            }
+        }
+
+        serial.extend(game.take_serial_output());
+        if let Some(outcome) = serial_outcome(&serial) {
+            return Ok(outcome);
+        }
+        if cycles >= next_frame {
+            next_frame += CYCLES_PER_FRAME;
+            if let Some(outcome) = memory_outcome(&game) {
+                return Ok(outcome);
+            }
+        }
+    }
+    Ok(Outcome::TimedOut)
+}
+
+fn mooneye_outcome(game: &Emulator) -> Option<Outcome> {
+    let registers = MOONEYE_REGISTERS.map(|register| game.registers().get_8(register));
+    match registers {
+        MOONEYE_PASSED => Some(Outcome::Passed),
+        MOONEYE_FAILED => Some(Outcome::Failed("Mooneye failure pattern".to_owned())),
+        _ => None,
+    }
+}
+
+/// Looks for a complete "Passed" or "Failed" line in the serial output.
+fn serial_outcome(serial: &[u8]) -> Option<Outcome> {
+    let text = String::from_utf8_lossy(serial);
+    let (complete, _) = text.rsplit_once('\n')?;
+    if complete.lines().any(|line| line.contains("Failed")) {
+        Some(Outcome::Failed(text.trim().to_owned()))
+    } else if complete.lines().any(|line| line.starts_with("Passed")) {
+        Some(Outcome::Passed)
+    } else {
+        None
+    }
+}
+
+/// Reads the status Blargg tests write at 0xA000, with the message as a C string at 0xA004.
+fn memory_outcome(game: &Emulator) -> Option<Outcome> {
+    let signature = [0xA001, 0xA002, 0xA003].map(|address| game.peek_8(address));
+    if signature != BLARGG_SIGNATURE {
+        return None;
+    }
+
+    match game.peek_8(0xA000) {
+        BLARGG_RUNNING | BLARGG_RESET_REQUESTED => None,
+        0x00 => Some(Outcome::Passed),
+        code => {
+            let message: Vec<u8> = (0xA004..0xBFFF)
+                .map(|address| game.peek_8(address))
+                .take_while(|&value| value != 0)
+                .collect();
+            let message = String::from_utf8_lossy(&message);
+            Some(Outcome::Failed(format!(
+                "result code {:#04X}: {}",
+                code,
+                message.trim()
+            )))
+        }
+    }
+}
+
+/// Every `.gb` file under `dir`, sorted by path.
+fn find_roms(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            roms.extend(find_roms(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+/// Runs one test ROM, or every test ROM under a directory, and prints the results.
+///
+/// Returns whether every test passed.
+pub fn run_all(
+    path: &Path,
+    model: Model,
+    boot_rom: Option<BootRom>,
+    max_cycles: ClockTicks,
+) -> anyhow::Result<bool> {
+    if !path.is_dir() {
+        let outcome = run(path, model, boot_rom, max_cycles)?;
+        println!("{} {}", outcome, path.display());
+        if let Outcome::Failed(message) = &outcome {
+            println!("{}", message);
+        }
+        return Ok(outcome.passed());
+    }
+
+    let roms = find_roms(path)?;
+    let mut passed = 0;
+    for rom in &roms {
+        let outcome = match run(rom, model, boot_rom.clone(), max_cycles) {
+            Ok(outcome) => outcome,
+            Err(err) => Outcome::Failed(err.to_string()),
+        };
+        let name = rom.strip_prefix(path).unwrap_or(rom).display();
+        match &outcome {
+            Outcome::Failed(message) => {
+                let summary = message.lines().last().unwrap_or_default();
+                println!("{:<8} {} ({})", outcome, name, summary)
+            }
+            _ => println!("{:<8} {}", outcome, name),
+        }
+        if outcome.passed() {
+            passed += 1;
+        }
+    }
+    println!("{}/{} tests passed on {}", passed, roms.len(), model);
+    Ok(passed == roms.len())
+}
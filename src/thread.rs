@@ -1,23 +1,38 @@
 use std::sync::Arc;
 
-use crate::{lr35902::Registers, ppu};
+use dmg_rs::{
+    clock::Rate, error::Fault, interrupt::InterruptState, joypad::DmgButton, lr35902::Registers,
+    ppu,
+};
+
+/// Position in the active movie.
+pub struct MovieStatus {
+    pub frame: usize,
+    pub length: usize,
+    pub read_only: bool,
+}
 
 pub enum DmgMessage {
     RegistersStatus(Registers),
+    InterruptState(InterruptState),
     MemoryState(Arc<[u8; 0x10000]>),
     Render(Arc<ppu::PixelBuffer>),
+    Fault(Fault),
+    /// Measured emulation speed, 1.0 being the speed of the hardware.
+    Speed(f64),
+    /// Screen saved in a save state slot.
+    SaveStateThumbnail(usize, Arc<ppu::PixelBuffer>),
+    Movie(Option<MovieStatus>),
+    /// The machine was reset, earlier faults no longer apply.
+    Reset,
+    /// Another ROM was inserted, its save state thumbnails follow.
+    RomLoaded,
 }
 
-#[derive(Debug)]
-pub enum DmgButton {
-    Up,
-    Down,
-    Left,
-    Right,
-    A,
-    B,
-    Start,
-    Select,
+pub enum MovieStart {
+    PowerOn,
+    /// Embeds a save state of the machine as it is.
+    CurrentState,
 }
 
 pub enum GuiMessage {
@@ -27,4 +42,25 @@ pub enum GuiMessage {
     RequestState,
     Close,
     StepMode(bool),
+    /// A frame was presented on the display.
+    VSync,
+    /// Speed to run at, relative to the hardware.
+    Rate(Rate),
+    /// Runs a single frame while in step mode.
+    NextFrame,
+    /// Plays the game backwards while set.
+    Rewind(bool),
+    SaveState(usize),
+    LoadState(usize),
+    RecordMovie(MovieStart),
+    ToggleMovieReadOnly,
+    StopMovie,
+    /// Resets the machine, keeping the content of work RAM.
+    Reset,
+    /// Turns the machine off and on again, optionally filling RAM with random values.
+    PowerCycle {
+        randomize_ram: bool,
+    },
+    /// Swaps the cartridge for the ROM at the given path.
+    LoadRom(String),
 }
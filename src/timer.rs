@@ -1,3 +1,8 @@
+use crate::{
+    save_state::{self, Reader, Snapshot, Writer},
+    scheduler::{Event, Scheduler, Timestamp},
+};
+
 #[derive(Default, Debug, Clone, Copy)]
 pub enum ClockType {
     #[default]
@@ -30,14 +35,16 @@ impl From<ClockType> for u8 {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Timer {
-    div_register: u8,
+    /// Internal 16 bit divider minus the current timestamp, DIV being the divider's upper byte.
+    divider_offset: u64,
+    /// TIMA, as of `synced_at`.
     counter: u8,
+    synced_at: Timestamp,
     modulo: u8,
     enable: bool,
     selected_clock: ClockType,
-    tick_counter: usize,
 }
 
 impl Timer {
@@ -47,41 +54,86 @@ impl Timer {
         }
     }
 
-    pub fn tick(&mut self) -> bool {
-        let mut raise_interrupt = false;
-        self.tick_counter = self.tick_counter.wrapping_add(1usize);
-        if self.tick_counter % 256 == 0 {
-            self.div_register = self.div_register.wrapping_add(1);
+    /// Starts with the internal 16 bit divider at the given value, DIV being its upper byte.
+    pub fn with_div(div: u16) -> Self {
+        Self {
+            divider_offset: div as u64,
+            ..Default::default()
         }
-        if self.enable && self.tick_counter % self.selected_clock as usize == 0 {
-            let value = self.counter.checked_add(1);
-            if let Some(val) = value {
-                self.counter = val;
-            } else {
-                self.counter = self.modulo;
-                raise_interrupt = true;
+    }
+
+    fn divider(&self, now: Timestamp) -> u64 {
+        now.wrapping_add(self.divider_offset)
+    }
+
+    /// Brings TIMA up to date, returns whether it overflowed on the way.
+    ///
+    /// TIMA increments every time the divider reaches a multiple of the selected period.
+    fn sync(&mut self, now: Timestamp) -> bool {
+        let mut increments = match self.enable {
+            true => {
+                let period = self.selected_clock as u64;
+                self.divider(now) / period - self.divider(self.synced_at) / period
             }
+            false => 0,
+        };
+        self.synced_at = now;
+
+        let mut overflowed = false;
+        while increments > 0 {
+            let to_overflow = 0x100 - self.counter as u64;
+            if increments < to_overflow {
+                self.counter += increments as u8;
+                break;
+            }
+            increments -= to_overflow;
+            self.counter = self.modulo;
+            overflowed = true;
         }
-        raise_interrupt
+        overflowed
+    }
+
+    /// Schedules the next TIMA overflow, with TIMA up to date as of `time`.
+    fn schedule_overflow(&self, time: Timestamp, scheduler: &mut Scheduler) {
+        if !self.enable {
+            scheduler.cancel(Event::TimerOverflow);
+            return;
+        }
+
+        let period = self.selected_clock as u64;
+        let divider = self.divider(time);
+        let increments = 0x100 - self.counter as u64;
+        let overflow = (divider / period + increments) * period;
+        scheduler.schedule(Event::TimerOverflow, time + (overflow - divider));
+    }
+
+    /// Handles the overflow event scheduled at `time`. TIMA gets reloaded from TMA and the timer
+    /// interrupt is to be requested.
+    pub fn overflow(&mut self, time: Timestamp, scheduler: &mut Scheduler) {
+        self.sync(time);
+        self.schedule_overflow(time, scheduler);
     }
 
     fn write_timer_control(&mut self, value: u8) {
-        self.enable = match value & 0x04 {
-            0 => false,
-            _ => true,
-        };
+        self.enable = value & 0x04 != 0;
 
         self.selected_clock = ClockType::from(value & 0x03);
     }
 
-    pub fn write_8(&mut self, address: u16, value: u8) {
+    pub fn write_8(&mut self, address: u16, value: u8, scheduler: &mut Scheduler) {
+        let now = scheduler.now();
+        self.sync(now);
         match address {
-            0xFF04 => self.div_register = 0u8,
+            0xFF04 => {
+                // DIV is the upper byte of the internal counter, which gets reset as a whole
+                self.divider_offset = 0u64.wrapping_sub(now);
+            }
             0xFF05 => self.counter = value,
             0xFF06 => self.modulo = value,
             0xFF07 => self.write_timer_control(value),
             _ => unreachable!(),
         }
+        self.schedule_overflow(now, scheduler);
     }
 
     fn read_timer_control(&self) -> u8 {
@@ -94,13 +146,38 @@ impl Timer {
         (enable << 2) | clock
     }
 
-    pub fn read_8(&self, address: u16) -> u8 {
+    pub fn read_8(&self, address: u16, now: Timestamp) -> u8 {
         match address {
-            0xFF04 => self.div_register,
-            0xFF05 => self.counter,
+            0xFF04 => (self.divider(now) >> 8) as u8,
+            0xFF05 => {
+                let mut timer = self.clone();
+                timer.sync(now);
+                timer.counter
+            }
             0xFF06 => self.modulo,
             0xFF07 => self.read_timer_control(),
             _ => unreachable!(),
         }
     }
 }
+
+impl Snapshot for Timer {
+    fn save(&self, state: &mut Writer) {
+        state.u64(self.divider_offset);
+        state.u8(self.counter);
+        state.u64(self.synced_at);
+        state.u8(self.modulo);
+        state.bool(self.enable);
+        state.u8(self.selected_clock.into());
+    }
+
+    fn load(&mut self, state: &mut Reader) -> save_state::Result<()> {
+        self.divider_offset = state.u64()?;
+        self.counter = state.u8()?;
+        self.synced_at = state.u64()?;
+        self.modulo = state.u8()?;
+        self.enable = state.bool()?;
+        self.selected_clock = ClockType::from(state.u8()? & 0x03);
+        Ok(())
+    }
+}
@@ -1,25 +1,25 @@
 #![allow(dead_code)]
-use std::{cell::Ref, collections::HashMap};
+use std::{collections::HashMap, fmt};
 
 use crate::{
+    bus::Bus,
     disassembler::{disassemble_one, Instruction},
-    mmu::MemoryMapUnit,
 };
 
-const _JUMP_INSTRUCTIONS: &'static [u8] = &[
+const _JUMP_INSTRUCTIONS: &[u8] = &[
     0x18, 0x20, 0x28, 0x30, 0x38, 0xC0, 0xC2, 0xC3, 0xC4, 0xC7, 0xC8, 0xC9, 0xCA, 0xCC, 0xCD, 0xCF,
     0xD0, 0xD2, 0xD4, 0xD8, 0xD9, 0xDA, 0xDC, 0xDF, 0xE7, 0xE9, 0xEF, 0xF7, 0xFF,
 ];
 
-const _CALL_INSTRUCTIONS_FULL: &'static [u8] = &[
+const _CALL_INSTRUCTIONS_FULL: &[u8] = &[
     0xC4, 0xC7, 0xCC, 0xCD, 0xCF, 0xD4, 0xD7, 0xDC, 0xDF, 0xE7, 0xEF, 0xF7, 0xFF,
 ];
 
-const CALL_INSTRUCTIONS: &'static [u8] = &[0xC4, 0xCC, 0xCD, 0xD4, 0xDC, 0xEF, 0xFF];
+const CALL_INSTRUCTIONS: &[u8] = &[0xC4, 0xCC, 0xCD, 0xD4, 0xDC, 0xEF, 0xFF];
 
-const _RET_INSTUCTIONS_FULL: &'static [u8] = &[0xC0, 0xC8, 0xC9, 0xD0, 0xD8, 0xD9];
+const _RET_INSTUCTIONS_FULL: &[u8] = &[0xC0, 0xC8, 0xC9, 0xD0, 0xD8, 0xD9];
 
-const RET_INSTUCTIONS: &'static [u8] = &[0xC0, 0xC8, 0xC9, 0xD0, 0xD8];
+const RET_INSTUCTIONS: &[u8] = &[0xC0, 0xC8, 0xC9, 0xD0, 0xD8];
 
 #[derive(Debug)]
 enum InstructionRole {
@@ -42,6 +42,18 @@ pub struct Tracer {
     current_depth: u32,
 }
 
+/// Disassembles the instruction at `pc` straight from the bus.
+fn disassemble_at(opcode: u8, pc: u16, bus: &impl Bus) -> Instruction {
+    let bytes = [
+        opcode,
+        bus.peek_8(pc.wrapping_add(1)),
+        bus.peek_8(pc.wrapping_add(2)),
+    ];
+    let mut instruction = disassemble_one(opcode, &mut 0, &bytes);
+    instruction.address = pc;
+    instruction
+}
+
 impl Tracer {
     pub fn new_call_tracer() -> Self {
         let mut to_trace: HashMap<u8, InstructionRole> = HashMap::new();
@@ -61,21 +73,23 @@ impl Tracer {
         }
     }
 
-    pub fn trace(&mut self, opcode: u8, pc: u16, memory: Ref<'_, MemoryMapUnit>) {
-        let tmp = pc - 1; // Needed bcz pc_next_8 is called before trace so the pc is offset
-        if let Some(_) = self.to_trace.get(&opcode) {
-            self.trace_opcode(opcode, pc - 1, memory);
-        } else if let Some(_) = self.pc_to_trace.get(&tmp) {
-            self.trace_address(opcode, pc - 1, memory);
+    pub fn trace(&mut self, opcode: u8, pc: u16, bus: &impl Bus) {
+        // Needed bcz pc_next_8 is called before trace so the pc is offset
+        let tmp = pc.wrapping_sub(1);
+        if self.to_trace.contains_key(&opcode) {
+            self.trace_opcode(opcode, tmp, bus);
+        } else if self.pc_to_trace.contains_key(&tmp) {
+            self.trace_address(opcode, tmp, bus);
         }
     }
 
-    fn trace_opcode(&mut self, opcode: u8, pc: u16, memory: Ref<'_, MemoryMapUnit>) {
-        let mut pc = pc;
-        let instruction = disassemble_one(opcode, &mut pc, memory.borrow_rom());
+    fn trace_opcode(&mut self, opcode: u8, pc: u16, bus: &impl Bus) {
+        let instruction = disassemble_at(opcode, pc, bus);
 
+        // Returns outnumber calls when tracing starts inside a subroutine, or when a game
+        // leaves one by popping the return address
         if let InstructionRole::Return = self.to_trace[&opcode] {
-            self.current_depth -= 1;
+            self.current_depth = self.current_depth.saturating_sub(1);
         }
 
         let trace = Trace {
@@ -90,9 +104,8 @@ impl Tracer {
         }
     }
 
-    fn trace_address(&mut self, opcode: u8, pc: u16, memory: Ref<'_, MemoryMapUnit>) {
-        let mut pc = pc;
-        let instruction = disassemble_one(opcode, &mut pc, memory.borrow_rom());
+    fn trace_address(&mut self, opcode: u8, pc: u16, bus: &impl Bus) {
+        let instruction = disassemble_at(opcode, pc, bus);
 
         self.traces.push(Trace {
             depth: self.current_depth,
@@ -101,18 +114,15 @@ impl Tracer {
     }
 }
 
-impl ToString for Tracer {
-    fn to_string(&self) -> String {
-        let mut res = String::new();
-        res.reserve(self.traces.len() * 20); // 20 is arbitrary number for the average length of the output per instruction
+impl fmt::Display for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for trace in &self.traces {
-            res.push_str(&format!("{:#06X}| ", trace.instruction.address));
+            write!(f, "{:#06X}| ", trace.instruction.address)?;
             for _ in 0..trace.depth {
-                res.push_str(". ");
+                write!(f, ". ")?;
             }
-            res.push_str(&trace.instruction.mnemonic);
-            res.push_str("\n");
+            writeln!(f, "{}", trace.instruction.mnemonic)?;
         }
-        res
+        Ok(())
     }
 }
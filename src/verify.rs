@@ -0,0 +1,58 @@
+use dmg_rs::{
+    boot_rom::BootRom,
+    hash_log::{self, FrameHashes, HashLog},
+    model::Model,
+    movie::Movie,
+    Emulator,
+};
+
+/// Plays a movie back and hashes the machine after every frame.
+///
+/// The hashes are compared with the log written next to the movie while recording it, with a
+/// `.hashes` extension, and the first frame that differs is reported.
+///
+/// Returns whether the run matches the log.
+pub fn verify(
+    rom_path: &str,
+    model: Model,
+    boot_rom: Option<BootRom>,
+    movie_path: &str,
+) -> anyhow::Result<bool> {
+    let movie = Movie::from_file(movie_path)?;
+    let mut game = Emulator::new(model, boot_rom);
+    game.load_rom(rom_path)?;
+    movie.start(&mut game)?;
+
+    let log_path = hash_log::log_path(movie_path);
+    if !log_path.exists() {
+        anyhow::bail!(
+            "{} has no hashes in {}, they are written while recording it",
+            movie_path,
+            log_path.display()
+        );
+    }
+    let expected = HashLog::from_file(&log_path.to_string_lossy())?;
+    if expected.frames.len() != movie.frames.len() {
+        println!(
+            "FAIL {} has {} frames, {} has {}",
+            movie_path,
+            movie.frames.len(),
+            log_path.display(),
+            expected.frames.len()
+        );
+        return Ok(false);
+    }
+
+    for (index, input) in movie.frames.iter().enumerate() {
+        game.set_buttons(&input.buttons());
+        game.run_frame();
+        let hashes = FrameHashes::of(&game)?;
+        if let Some(divergence) = expected.check(index + 1, &hashes) {
+            println!("FAIL {} diverges at {}", movie_path, divergence);
+            return Ok(false);
+        }
+    }
+
+    println!("PASS {} matches {}", movie_path, log_path.display());
+    Ok(true)
+}
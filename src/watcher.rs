@@ -0,0 +1,54 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use tracing::warn;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Polls a file's modification time to detect when it has been rebuilt.
+#[derive(Debug)]
+pub struct RomWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl RomWatcher {
+    pub fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let last_modified = Self::modified(&path);
+        Self {
+            path,
+            last_modified,
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn modified(path: &PathBuf) -> Option<SystemTime> {
+        match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(time) => Some(time),
+            Err(err) => {
+                warn!("Could not stat {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Returns true once per change of the file on disk.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = Self::modified(&self.path);
+        if modified.is_none() || modified == self.last_modified {
+            return false;
+        }
+        self.last_modified = modified;
+        true
+    }
+}